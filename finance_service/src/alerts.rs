@@ -0,0 +1,345 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{Mutex, RwLock};
+use utils::{database::{PgPool, alerts::{AlertEvent, AlertRuleRow, delete_alert_rule, get_alert_rules, insert_alert_event, insert_alert_rule, set_alert_triggered}, finance::Decimal, watchlists::Uuid}, log::{error, info, warn}};
+
+use crate::{book::PriceBook, calendar, subscriptions::normalize_symbol, types::{AssetClass, FinanceState, PriceUpdate}};
+
+const WEBHOOK_URL_VAR: &str = "FINANCE_ALERT_WEBHOOK_URL";
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_ALERT_COOLDOWN_SECS: i32 = 3600;
+pub const MIN_ALERT_COOLDOWN_SECS: i32 = 60;
+
+/// What an alert rule watches for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The price moves from below `price` to at or above it.
+    PriceAbove { price: Decimal },
+    /// The price moves from above `price` to at or below it.
+    PriceBelow { price: Decimal },
+    /// The price is `percent` or more away from the previous close, in either direction.
+    PercentMove { percent: Decimal },
+    /// The price exceeds every earlier trade of the trading day.
+    IntradayHigh,
+}
+
+impl AlertCondition {
+    fn kind(&self) -> &'static str {
+        match self {
+            AlertCondition::PriceAbove { .. } => "price_above",
+            AlertCondition::PriceBelow { .. } => "price_below",
+            AlertCondition::PercentMove { .. } => "percent_move",
+            AlertCondition::IntradayHigh => "intraday_high",
+        }
+    }
+
+    fn threshold(&self) -> Option<Decimal> {
+        match self {
+            AlertCondition::PriceAbove { price } | AlertCondition::PriceBelow { price } => Some(*price),
+            AlertCondition::PercentMove { percent } => Some(*percent),
+            AlertCondition::IntradayHigh => None,
+        }
+    }
+
+    fn from_row(kind: &str, threshold: Option<Decimal>) -> Option<Self> {
+        match (kind, threshold) {
+            ("price_above", Some(price)) => Some(AlertCondition::PriceAbove { price }),
+            ("price_below", Some(price)) => Some(AlertCondition::PriceBelow { price }),
+            ("percent_move", Some(percent)) => Some(AlertCondition::PercentMove { percent }),
+            ("intraday_high", _) => Some(AlertCondition::IntradayHigh),
+            _ => None,
+        }
+    }
+}
+
+/// Public representation of an alert rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub id: Uuid,
+    pub symbol: String,
+    pub condition: AlertCondition,
+    pub cooldown_secs: i32,
+    pub last_triggered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Alert {
+    fn from_row(row: AlertRuleRow) -> Option<Self> {
+        let Some(condition) = AlertCondition::from_row(&row.kind, row.threshold) else {
+            warn!("Ignoring alert rule {} with unknown kind {}", row.id, row.kind);
+            return None;
+        };
+
+        Some(Self {
+            id: row.id,
+            symbol: row.symbol,
+            condition,
+            cooldown_secs: row.cooldown_secs,
+            last_triggered_at: row.last_triggered_at,
+            created_at: row.created_at,
+        })
+    }
+}
+
+struct LoadedRule {
+    user_id: String,
+    alert: Alert,
+}
+
+#[derive(Default)]
+struct EngineState {
+    last_prices: HashMap<String, Decimal>,
+    day_highs: HashMap<String, (NaiveDate, Decimal)>,
+    last_triggered: HashMap<Uuid, DateTime<Utc>>,
+}
+
+/// Evaluates every user's alert rules against processed trade batches.
+pub struct AlertEngine {
+    pool: Arc<PgPool>,
+    book: Arc<PriceBook>,
+    client: Client,
+    webhook_url: Option<String>,
+    rules: RwLock<HashMap<String, Vec<LoadedRule>>>,
+    state: Mutex<EngineState>,
+}
+
+impl AlertEngine {
+    pub fn new(pool: Arc<PgPool>, book: Arc<PriceBook>) -> Self {
+        let client = Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build().expect("Failed creating alert webhook Reqwest Client");
+
+        Self {
+            pool,
+            book,
+            client,
+            webhook_url: env::var(WEBHOOK_URL_VAR).ok().filter(|url| !url.is_empty()),
+            rules: RwLock::new(HashMap::new()),
+            state: Mutex::new(EngineState::default()),
+        }
+    }
+
+    /// Re-reads every rule from the database, run after any rule changes.
+    pub async fn reload(&self) {
+        let mut rules: HashMap<String, Vec<LoadedRule>> = HashMap::new();
+
+        for row in get_alert_rules(self.pool.clone(), None).await {
+            let user_id = row.user_id.clone();
+            if let Some(alert) = Alert::from_row(row) {
+                rules.entry(alert.symbol.clone()).or_default().push(LoadedRule { user_id, alert });
+            }
+        }
+
+        let count: usize = rules.values().map(Vec::len).sum();
+        *self.rules.write().await = rules;
+
+        info!("Loaded {count} alert rules");
+    }
+
+    /// Checks the rules of every updated symbol, triggered alerts are delivered in the background.
+    pub(crate) async fn evaluate(&self, updates: &[PriceUpdate]) {
+        let rules = self.rules.read().await;
+        let mut state = self.state.lock().await;
+
+        self.seed_day_highs(&rules, &mut state, updates).await;
+
+        let now = Utc::now();
+        let mut triggered = Vec::new();
+
+        for update in updates {
+            let previous_price = state.last_prices.insert(update.symbol.clone(), update.price);
+
+            let trading_day = calendar::trading_day(AssetClass::from_symbol(&update.symbol), update.traded_at);
+            let previous_high = match state.day_highs.get(&update.symbol) {
+                Some((day, high)) if *day == trading_day => Some(*high),
+                _ => None,
+            };
+
+            if let Some(high) = previous_high && update.price > high {
+                state.day_highs.insert(update.symbol.clone(), (trading_day, update.price));
+            }
+
+            for rule in rules.get(&update.symbol).into_iter().flatten() {
+                let Some(message) = check(&rule.alert.condition, update, previous_price, previous_high) else { continue };
+
+                let last_triggered = state.last_triggered.get(&rule.alert.id).copied().or(rule.alert.last_triggered_at);
+                if last_triggered.is_some_and(|at| now - at < chrono::Duration::seconds(rule.alert.cooldown_secs.into())) {
+                    continue;
+                }
+
+                state.last_triggered.insert(rule.alert.id, now);
+
+                triggered.push(AlertEvent {
+                    id: 0,
+                    rule_id: rule.alert.id,
+                    user_id: rule.user_id.clone(),
+                    symbol: update.symbol.clone(),
+                    kind: rule.alert.condition.kind().to_string(),
+                    price: update.price,
+                    percentage_change: update.percentage_change,
+                    message,
+                    triggered_at: now,
+                    delivered: false,
+                    delivery_error: None,
+                });
+            }
+        }
+
+        for event in triggered {
+            info!("Alert {} triggered: {}", event.rule_id, event.message);
+            tokio::spawn(deliver(self.pool.clone(), self.client.clone(), self.webhook_url.clone(), event));
+        }
+    }
+
+    /// Day highs of symbols with intraday high rules are taken from the price book
+    /// the first time they are needed on a trading day, it already holds this batch.
+    async fn seed_day_highs(&self, rules: &HashMap<String, Vec<LoadedRule>>, state: &mut EngineState, updates: &[PriceUpdate]) {
+        let mut symbols = Vec::new();
+        let mut days = HashMap::new();
+
+        for update in updates {
+            let watches_highs = rules.get(&update.symbol)
+                .is_some_and(|rules| rules.iter().any(|rule| rule.alert.condition == AlertCondition::IntradayHigh));
+            if !watches_highs {
+                continue;
+            }
+
+            let asset_class = AssetClass::from_symbol(&update.symbol);
+            let trading_day = calendar::trading_day(asset_class, update.traded_at);
+            if state.day_highs.get(&update.symbol).is_some_and(|(day, _)| *day == trading_day) {
+                continue;
+            }
+
+            symbols.push(update.symbol.clone());
+            days.insert(update.symbol.clone(), (trading_day, update.price));
+        }
+
+        if symbols.is_empty() {
+            return;
+        }
+
+        let rows = self.book.get_many(&symbols).await;
+
+        for (symbol, (day, price)) in days {
+            let high = rows.get(&symbol)
+                .filter(|row| row.session_date == Some(day))
+                .and_then(|row| row.day_high)
+                .map_or(price, |high| high.max(price));
+
+            state.day_highs.insert(symbol, (day, high));
+        }
+    }
+}
+
+/// The alert message if `update` satisfies `condition`.
+fn check(condition: &AlertCondition, update: &PriceUpdate, previous_price: Option<Decimal>, previous_high: Option<Decimal>) -> Option<String> {
+    let symbol = &update.symbol;
+    let price = update.price;
+
+    match *condition {
+        AlertCondition::PriceAbove { price: target } => {
+            (previous_price? < target && price >= target).then(|| format!("{symbol} crossed above {target} at {price}"))
+        }
+        AlertCondition::PriceBelow { price: target } => {
+            (previous_price? > target && price <= target).then(|| format!("{symbol} crossed below {target} at {price}"))
+        }
+        AlertCondition::PercentMove { percent } => {
+            (update.percentage_change.abs() >= percent).then(|| format!("{symbol} moved {:+}% from the previous close to {price}", update.percentage_change))
+        }
+        AlertCondition::IntradayHigh => {
+            (price > previous_high?).then(|| format!("{symbol} hit a new intraday high of {price}"))
+        }
+    }
+}
+
+async fn deliver(pool: Arc<PgPool>, client: Client, webhook_url: Option<String>, mut event: AlertEvent) {
+    set_alert_triggered(pool.clone(), event.rule_id, event.triggered_at).await;
+
+    if let Some(url) = webhook_url {
+        let payload = json!({
+            "rule_id": event.rule_id,
+            "user_id": event.user_id,
+            "symbol": event.symbol,
+            "kind": event.kind,
+            "price": event.price,
+            "percentage_change": event.percentage_change,
+            "message": event.message,
+            "triggered_at": event.triggered_at,
+        });
+
+        let result = client.post(&url).json(&payload).send().await.and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => event.delivered = true,
+            Err(e) => {
+                error!("Failed delivering alert {} to the webhook: {e}", event.rule_id);
+                event.delivery_error = Some(e.to_string());
+            }
+        }
+    } else {
+        event.delivery_error = Some(format!("{WEBHOOK_URL_VAR} is not set"));
+    }
+
+    insert_alert_event(pool, event).await;
+}
+
+/// Stores a new rule for a live symbol and starts evaluating it.
+pub async fn create_alert(state: &FinanceState, user_id: String, symbol: &str, condition: AlertCondition, cooldown_secs: Option<i32>) -> Result<Alert> {
+    let symbol = normalize_symbol(symbol)?;
+
+    if !state.current_subscriptions().await.contains(&symbol) {
+        bail!("{symbol} is not tracked, add it to a watchlist first");
+    }
+
+    if condition.threshold().is_some_and(|threshold| threshold <= Decimal::ZERO) {
+        bail!("Alert thresholds must be positive");
+    }
+
+    let cooldown_secs = cooldown_secs.unwrap_or(DEFAULT_ALERT_COOLDOWN_SECS);
+    if cooldown_secs < MIN_ALERT_COOLDOWN_SECS {
+        bail!("Alert cooldowns must be at least {MIN_ALERT_COOLDOWN_SECS} seconds");
+    }
+
+    let row = AlertRuleRow {
+        id: Uuid::new_v4(),
+        user_id,
+        symbol,
+        kind: condition.kind().to_string(),
+        threshold: condition.threshold(),
+        cooldown_secs,
+        last_triggered_at: None,
+        created_at: Utc::now(),
+    };
+
+    if !insert_alert_rule(state.pool.clone(), row.clone()).await {
+        bail!("Failed to store the alert");
+    }
+
+    state.alerts.reload().await;
+
+    Alert::from_row(row).ok_or_else(|| anyhow::anyhow!("Stored an alert of an unknown kind"))
+}
+
+pub async fn list_alerts(state: &FinanceState, user_id: String) -> Vec<Alert> {
+    get_alert_rules(state.pool.clone(), Some(user_id)).await
+        .into_iter()
+        .filter_map(Alert::from_row)
+        .collect()
+}
+
+/// Returns `true` if the rule existed and belonged to `user_id`.
+pub async fn delete_alert(state: &FinanceState, id: Uuid, user_id: String) -> bool {
+    let deleted = delete_alert_rule(state.pool.clone(), id, user_id).await;
+
+    if deleted {
+        state.alerts.reload().await;
+    }
+
+    deleted
+}
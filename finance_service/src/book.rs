@@ -0,0 +1,324 @@
+use std::{collections::{HashMap, HashSet}, mem, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+
+use tokio::{sync::{Mutex, Notify, RwLock}, time::sleep};
+use utils::{database::{PgPool, finance::{DatabaseTradeData, Decimal, NaiveDate, SymbolMetadata, TradeBatchRow, TradeHistoryRow, Utc, get_symbol_metadata, get_trades, store_trades}}, log::{debug, info, warn}};
+
+use crate::types::FinanceHealth;
+
+const MIN_PRICE_SCALE: i16 = 2;
+
+/// History rows held while the database is unreachable, past this each symbol's
+/// rows are merged into one so the backlog stays bounded by the symbol count.
+const MAX_PENDING_HISTORY: usize = 10_000;
+const PERSIST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_PERSIST_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Changes not yet in the database, coalesced per symbol.
+#[derive(Default)]
+struct PendingWrites {
+    /// Symbols whose row changed, stored as the book holds them at write time.
+    symbols: HashSet<String>,
+    history: Vec<TradeHistoryRow>,
+}
+
+impl PendingWrites {
+    fn push_history(&mut self, row: TradeHistoryRow) {
+        if self.history.len() >= MAX_PENDING_HISTORY {
+            self.merge_history();
+        }
+
+        self.history.push(row);
+    }
+
+    /// Folds the rows of every symbol into one at its latest price, keeping the range and summing volume and count.
+    fn merge_history(&mut self) {
+        let mut merged: Vec<TradeHistoryRow> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+
+        for row in self.history.drain(..) {
+            match positions.get(&row.symbol) {
+                Some(&position) => {
+                    let kept = &mut merged[position];
+                    kept.high = kept.high.max(row.high);
+                    kept.low = kept.low.min(row.low);
+                    kept.volume += row.volume;
+                    kept.trade_count += row.trade_count;
+
+                    if row.traded_at < kept.traded_at {
+                        kept.open = row.open;
+                    } else {
+                        kept.price = row.price;
+                        kept.traded_at = row.traded_at;
+                    }
+                }
+                None => {
+                    positions.insert(row.symbol.clone(), merged.len());
+                    merged.push(row);
+                }
+            }
+        }
+
+        self.history = merged;
+    }
+
+    /// Puts back a write that failed, ahead of the changes made since.
+    fn restore(&mut self, symbols: HashSet<String>, history: Vec<TradeHistoryRow>) {
+        self.symbols.extend(symbols);
+
+        for row in mem::replace(&mut self.history, history) {
+            self.push_history(row);
+        }
+    }
+}
+
+/// Latest price row of every symbol, authoritative while the service runs.
+///
+/// Loaded from the `trades` table once at startup and updated in place, the
+/// changed rows are written back by [`PriceBook::persist`] so neither the
+/// pipeline nor the API waits for the database. Symbol metadata is kept
+/// alongside, it is stored by the metadata refresh itself.
+pub struct PriceBook {
+    pool: Arc<PgPool>,
+    entries: RwLock<HashMap<String, DatabaseTradeData>>,
+    metadata: RwLock<HashMap<String, SymbolMetadata>>,
+    pending: Mutex<PendingWrites>,
+    changed: Notify,
+    persisting: AtomicBool,
+}
+
+fn empty_row(symbol: &str) -> DatabaseTradeData {
+    DatabaseTradeData {
+        symbol: symbol.to_string(),
+        price: Decimal::ZERO,
+        previous_close: Decimal::ZERO,
+        price_change: Decimal::ZERO,
+        percentage_change: Decimal::ZERO,
+        direction: String::from("up"),
+        last_updated: Utc::now(),
+        session_date: None,
+        price_scale: MIN_PRICE_SCALE,
+        day_open: None,
+        day_high: None,
+        day_low: None,
+        day_volume: Decimal::ZERO,
+    }
+}
+
+impl PriceBook {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self {
+            pool,
+            entries: RwLock::new(HashMap::new()),
+            metadata: RwLock::new(HashMap::new()),
+            pending: Mutex::new(PendingWrites::default()),
+            changed: Notify::new(),
+            persisting: AtomicBool::new(false),
+        }
+    }
+
+    /// Replaces the book with the stored rows and their metadata.
+    pub async fn load(&self) {
+        let rows: HashMap<String, DatabaseTradeData> = get_trades(self.pool.clone()).await
+            .into_iter()
+            .map(|row| (row.symbol.clone(), row))
+            .collect();
+
+        let metadata: HashMap<String, SymbolMetadata> = get_symbol_metadata(self.pool.clone(), rows.keys().cloned().collect()).await
+            .into_iter()
+            .map(|metadata| (metadata.symbol.clone(), metadata))
+            .collect();
+
+        info!("Loaded {} symbols into the price book", rows.len());
+        *self.entries.write().await = rows;
+        *self.metadata.write().await = metadata;
+    }
+
+    pub async fn get(&self, symbol: &str) -> Option<DatabaseTradeData> {
+        self.entries.read().await.get(symbol).cloned()
+    }
+
+    /// Every row, ordered by symbol.
+    pub async fn get_all(&self) -> Vec<DatabaseTradeData> {
+        let mut rows: Vec<DatabaseTradeData> = self.entries.read().await.values().cloned().collect();
+        rows.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        rows
+    }
+
+    /// Rows for just the given symbols, unknown symbols are left out.
+    pub async fn get_many(&self, symbols: &[String]) -> HashMap<String, DatabaseTradeData> {
+        let entries = self.entries.read().await;
+
+        symbols.iter()
+            .filter_map(|symbol| entries.get(symbol).map(|row| (symbol.clone(), row.clone())))
+            .collect()
+    }
+
+    /// Metadata of the given symbols, symbols without any are left out.
+    pub async fn get_metadata(&self, symbols: &[String]) -> HashMap<String, SymbolMetadata> {
+        let metadata = self.metadata.read().await;
+
+        symbols.iter()
+            .filter_map(|symbol| metadata.get(symbol).map(|metadata| (symbol.clone(), metadata.clone())))
+            .collect()
+    }
+
+    pub(crate) async fn set_metadata(&self, metadata: SymbolMetadata) {
+        self.metadata.write().await.insert(metadata.symbol.clone(), metadata);
+    }
+
+    async fn mark_changed(&self, symbols: impl IntoIterator<Item = String>, history: Vec<TradeHistoryRow>) {
+        {
+            let mut pending = self.pending.lock().await;
+            pending.symbols.extend(symbols);
+
+            for row in history {
+                pending.push_history(row);
+            }
+        }
+
+        self.changed.notify_one();
+    }
+
+    pub(crate) async fn insert_symbol(&self, symbol: &str) {
+        self.entries.write().await.entry(symbol.to_string()).or_insert_with(|| empty_row(symbol));
+        self.mark_changed([symbol.to_string()], Vec::new()).await;
+    }
+
+    pub(crate) async fn remove_symbol(&self, symbol: &str) {
+        self.entries.write().await.remove(symbol);
+        self.metadata.write().await.remove(symbol);
+        self.mark_changed([symbol.to_string()], Vec::new()).await;
+    }
+
+    /// Folds a batch into the rows, a new trading day starts the range over.
+    ///
+    /// Symbols without a row were removed while their trades were queued or in flight,
+    /// their trades are dropped so the symbol is not written back.
+    pub(crate) async fn apply_batch(&self, rows: Vec<TradeBatchRow>) {
+        if rows.is_empty() {
+            return;
+        }
+
+        let rows: Vec<TradeBatchRow> = {
+            let mut entries = self.entries.write().await;
+            let now = Utc::now();
+
+            rows.into_iter().filter(|row| {
+                let Some(entry) = entries.get_mut(&row.symbol) else {
+                    debug!("Dropped a trade of {}, it is no longer tracked", row.symbol);
+                    return false;
+                };
+                let same_day = entry.session_date == Some(row.session_date);
+
+                entry.price = row.price;
+                entry.previous_close = row.previous_close;
+                entry.price_change = row.price_change;
+                entry.percentage_change = row.percentage_change;
+                entry.direction = row.direction.clone();
+                entry.price_scale = entry.price_scale.max(row.price_scale);
+                entry.last_updated = now;
+
+                if same_day {
+                    entry.day_open = entry.day_open.or(Some(row.open));
+                    entry.day_high = Some(entry.day_high.map_or(row.high, |high| high.max(row.high)));
+                    entry.day_low = Some(entry.day_low.map_or(row.low, |low| low.min(row.low)));
+                    entry.day_volume += row.volume;
+                } else {
+                    entry.session_date = Some(row.session_date);
+                    entry.day_open = Some(row.open);
+                    entry.day_high = Some(row.high);
+                    entry.day_low = Some(row.low);
+                    entry.day_volume = row.volume;
+                }
+
+                true
+            }).collect()
+        };
+
+        let history = rows.iter().map(|row| TradeHistoryRow {
+            symbol: row.symbol.clone(),
+            price: row.price,
+            open: row.open,
+            high: row.high,
+            low: row.low,
+            volume: row.volume,
+            trade_count: row.trade_count,
+            traded_at: row.traded_at,
+        }).collect();
+
+        self.mark_changed(rows.into_iter().map(|row| row.symbol), history).await;
+    }
+
+    /// A new trading day clears the day's range, unknown symbols are ignored.
+    pub(crate) async fn set_previous_close(&self, symbol: &str, previous_close: Decimal, session_date: NaiveDate) {
+        if let Some(entry) = self.entries.write().await.get_mut(symbol) {
+            if entry.session_date != Some(session_date) {
+                entry.day_open = None;
+                entry.day_high = None;
+                entry.day_low = None;
+                entry.day_volume = Decimal::ZERO;
+            }
+
+            entry.previous_close = previous_close;
+            entry.session_date = Some(session_date);
+        }
+
+        self.mark_changed([symbol.to_string()], Vec::new()).await;
+    }
+
+    /// Unknown symbols are ignored.
+    pub(crate) async fn set_price(&self, symbol: &str, price: Decimal, price_change: Decimal, percentage_change: Decimal, direction: &'static str) {
+        if let Some(entry) = self.entries.write().await.get_mut(symbol) {
+            entry.price = price;
+            entry.price_change = price_change;
+            entry.percentage_change = percentage_change;
+            entry.direction = direction.to_string();
+            entry.last_updated = Utc::now();
+        }
+
+        self.mark_changed([symbol.to_string()], Vec::new()).await;
+    }
+
+    /// Writes the changed rows and new history to the database for the lifetime of the service.
+    /// Failed writes are retried with backoff and counted as batch errors, nothing is dropped.
+    pub async fn persist(&self, health_state: Arc<Mutex<FinanceHealth>>) {
+        if self.persisting.swap(true, Ordering::AcqRel) {
+            warn!("The price book is already being persisted");
+            return;
+        }
+
+        let mut delay = PERSIST_RETRY_DELAY;
+
+        loop {
+            let (symbols, history) = {
+                let mut pending = self.pending.lock().await;
+                (mem::take(&mut pending.symbols), mem::take(&mut pending.history))
+            };
+
+            if symbols.is_empty() && history.is_empty() {
+                self.changed.notified().await;
+                continue;
+            }
+
+            let (rows, removed) = {
+                let entries = self.entries.read().await;
+                let (present, removed): (Vec<&String>, Vec<&String>) = symbols.iter().partition(|symbol| entries.contains_key(*symbol));
+
+                (present.into_iter().map(|symbol| entries[symbol].clone()).collect(), removed.into_iter().cloned().collect())
+            };
+
+            if store_trades(self.pool.clone(), rows, removed, history.clone()).await {
+                delay = PERSIST_RETRY_DELAY;
+                continue;
+            }
+
+            warn!("Failed writing {} symbols and {} trades, retrying in {delay:?}", symbols.len(), history.len());
+            health_state.lock().await.record_persist_failure();
+            self.pending.lock().await.restore(symbols, history);
+
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_PERSIST_RETRY_DELAY);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! NYSE / NASDAQ trading calendar, crypto (`BINANCE:`) symbols trade around the clock.
+
+use std::env;
+
+use chrono::{DateTime, Datelike, Days, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::America::New_York;
+use serde::Serialize;
+use utils::log::warn;
+
+use crate::types::AssetClass;
+
+const PRE_MARKET_OPEN: NaiveTime = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+const REGULAR_OPEN: NaiveTime = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+const REGULAR_CLOSE: NaiveTime = NaiveTime::from_hms_opt(16, 0, 0).unwrap();
+const EARLY_CLOSE: NaiveTime = NaiveTime::from_hms_opt(13, 0, 0).unwrap();
+const AFTER_HOURS_CLOSE: NaiveTime = NaiveTime::from_hms_opt(20, 0, 0).unwrap();
+const EARLY_AFTER_HOURS_CLOSE: NaiveTime = NaiveTime::from_hms_opt(17, 0, 0).unwrap();
+
+const CRYPTO_REFERENCE_VAR: &str = "FINANCE_CRYPTO_REFERENCE";
+
+/// One-off closures that do not follow the regular holiday rules.
+const SPECIAL_CLOSURES: [(i32, u32, u32); 1] = [
+    (2025, 1, 9), // National Day of Mourning, President Carter
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketSession {
+    PreMarket,
+    Regular,
+    AfterHours,
+    Closed,
+}
+
+impl MarketSession {
+    pub fn is_open(self) -> bool {
+        self == MarketSession::Regular
+    }
+}
+
+/// Session a symbol of `asset_class` is in at `at`.
+pub fn session(asset_class: AssetClass, at: DateTime<Utc>) -> MarketSession {
+    if asset_class == AssetClass::Crypto {
+        return MarketSession::Regular;
+    }
+
+    let local = at.with_timezone(&New_York);
+    let date = local.date_naive();
+
+    if !is_trading_day(date) {
+        return MarketSession::Closed;
+    }
+
+    let (close, after_hours_close) = if is_early_close(date) {
+        (EARLY_CLOSE, EARLY_AFTER_HOURS_CLOSE)
+    } else {
+        (REGULAR_CLOSE, AFTER_HOURS_CLOSE)
+    };
+
+    match local.time() {
+        time if time < PRE_MARKET_OPEN => MarketSession::Closed,
+        time if time < REGULAR_OPEN => MarketSession::PreMarket,
+        time if time < close => MarketSession::Regular,
+        time if time < after_hours_close => MarketSession::AfterHours,
+        _ => MarketSession::Closed,
+    }
+}
+
+/// Trading day whose price changes are shown at `at`.
+///
+/// Stocks roll over to a new trading day when its pre-market opens and keep
+/// the last one through nights, weekends and holidays. Crypto rolls over at
+/// midnight UTC.
+pub fn trading_day(asset_class: AssetClass, at: DateTime<Utc>) -> NaiveDate {
+    if asset_class == AssetClass::Crypto {
+        return at.date_naive();
+    }
+
+    let local = at.with_timezone(&New_York);
+    let mut date = local.date_naive();
+
+    if local.time() < PRE_MARKET_OPEN {
+        date = previous_day(date);
+    }
+
+    while !is_trading_day(date) {
+        date = previous_day(date);
+    }
+
+    date
+}
+
+/// When `trading_day` begins, pre-market open for stocks and midnight UTC for crypto.
+pub fn trading_day_start(asset_class: AssetClass, trading_day: NaiveDate) -> DateTime<Utc> {
+    match asset_class {
+        AssetClass::Stock => trading_day.and_time(PRE_MARKET_OPEN)
+            .and_local_timezone(New_York)
+            .earliest()
+            .expect("Pre-market open is never skipped by a DST change")
+            .with_timezone(&Utc),
+        AssetClass::Crypto => trading_day.and_time(NaiveTime::MIN).and_utc(),
+    }
+}
+
+/// What crypto price changes are measured against, continuous markets have no close.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoReference {
+    /// Last price before midnight UTC, the default.
+    UtcMidnight,
+    /// Last price 24 hours ago, as on most exchanges' tickers.
+    Rolling24h,
+}
+
+impl CryptoReference {
+    /// Reads `FINANCE_CRYPTO_REFERENCE`, either `utc_midnight` or `rolling_24h`.
+    pub fn from_env() -> Self {
+        match env::var(CRYPTO_REFERENCE_VAR).as_deref() {
+            Ok("rolling_24h") => CryptoReference::Rolling24h,
+            Ok("utc_midnight" | "") | Err(_) => CryptoReference::UtcMidnight,
+            Ok(other) => {
+                warn!("Invalid {CRYPTO_REFERENCE_VAR} {other}, using utc_midnight");
+                CryptoReference::UtcMidnight
+            }
+        }
+    }
+
+    /// Moment whose last traded price is the reference at `now`.
+    pub fn reference_time(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            CryptoReference::UtcMidnight => trading_day_start(AssetClass::Crypto, trading_day(AssetClass::Crypto, now)),
+            CryptoReference::Rolling24h => now - Duration::hours(24),
+        }
+    }
+}
+
+/// Calendar date of `at` on the exchange the asset class trades on.
+pub fn exchange_date(asset_class: AssetClass, at: DateTime<Utc>) -> NaiveDate {
+    match asset_class {
+        AssetClass::Stock => at.with_timezone(&New_York).date_naive(),
+        AssetClass::Crypto => at.date_naive(),
+    }
+}
+
+pub fn is_trading_day(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !is_holiday(date)
+}
+
+fn is_holiday(date: NaiveDate) -> bool {
+    let year = date.year();
+
+    if SPECIAL_CLOSURES.contains(&(year, date.month(), date.day())) {
+        return true;
+    }
+
+    let mut holidays = vec![
+        nth_weekday(year, 1, Weekday::Mon, 3),  // Martin Luther King Jr. Day
+        nth_weekday(year, 2, Weekday::Mon, 3),  // Washington's Birthday
+        easter_sunday(year) - Days::new(2),     // Good Friday
+        last_weekday(year, 5, Weekday::Mon),    // Memorial Day
+        observed(ymd(year, 7, 4)),              // Independence Day
+        nth_weekday(year, 9, Weekday::Mon, 1),  // Labor Day
+        nth_weekday(year, 11, Weekday::Thu, 4), // Thanksgiving
+        observed(ymd(year, 12, 25)),            // Christmas
+    ];
+
+    // A Saturday New Year's Day is not made up on the preceding Friday.
+    let new_year = ymd(year, 1, 1);
+    if new_year.weekday() != Weekday::Sat {
+        holidays.push(observed(new_year));
+    }
+
+    if year >= 2022 {
+        holidays.push(observed(ymd(year, 6, 19))); // Juneteenth
+    }
+
+    holidays.contains(&date)
+}
+
+/// Trading days that close at 13:00 ET.
+///
+/// July 3 only closes early when Independence Day falls on a Tuesday through Friday,
+/// a Saturday holiday is observed on July 3 itself and a Sunday or Monday one leaves
+/// the Friday before a regular day.
+fn is_early_close(date: NaiveDate) -> bool {
+    let year = date.year();
+
+    let mut early_closes = vec![
+        nth_weekday(year, 11, Weekday::Thu, 4) + Days::new(1),
+        ymd(year, 12, 24),
+    ];
+
+    if matches!(ymd(year, 7, 4).weekday(), Weekday::Tue | Weekday::Wed | Weekday::Thu | Weekday::Fri) {
+        early_closes.push(ymd(year, 7, 3));
+    }
+
+    early_closes.contains(&date) && is_trading_day(date)
+}
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).expect("Holiday rules only produce valid dates")
+}
+
+fn previous_day(date: NaiveDate) -> NaiveDate {
+    date - Days::new(1)
+}
+
+/// Weekend holidays move to the closest weekday.
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Days::new(1),
+        Weekday::Sun => date + Days::new(1),
+        _ => date,
+    }
+}
+
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).expect("Every month has at least four of each weekday")
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5).unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+/// Anonymous Gregorian algorithm.
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    ymd(year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn full_closures() {
+        let closed = [
+            ymd(2026, 1, 1),   // New Year's Day
+            ymd(2026, 1, 19),  // Martin Luther King Jr. Day
+            ymd(2026, 2, 16),  // Washington's Birthday
+            ymd(2026, 4, 3),   // Good Friday
+            ymd(2026, 5, 25),  // Memorial Day
+            ymd(2026, 6, 19),  // Juneteenth
+            ymd(2026, 7, 3),   // Independence Day on a Saturday, observed on Friday
+            ymd(2026, 9, 7),   // Labor Day
+            ymd(2026, 11, 26), // Thanksgiving
+            ymd(2026, 12, 25), // Christmas
+            ymd(2027, 7, 5),   // Independence Day on a Sunday, observed on Monday
+            ymd(2021, 12, 24), // Christmas on a Saturday, observed on Friday
+            ymd(2022, 12, 26), // Christmas on a Sunday, observed on Monday
+            ymd(2025, 1, 9),   // National Day of Mourning
+        ];
+
+        for date in closed {
+            assert!(!is_trading_day(date), "{date} should be closed");
+            assert!(!is_early_close(date), "{date} is closed, not closing early");
+        }
+
+        let open = [
+            ymd(2021, 12, 31), // New Year's Day on a Saturday is not made up on Friday
+            ymd(2021, 6, 18),  // Juneteenth before it became a market holiday
+            ymd(2026, 7, 6),
+        ];
+
+        for date in open {
+            assert!(is_trading_day(date), "{date} should be open");
+        }
+    }
+
+    #[test]
+    fn early_closes() {
+        let early = [
+            ymd(2026, 11, 27), // Day after Thanksgiving
+            ymd(2026, 12, 24), // Christmas Eve on a Thursday
+            ymd(2018, 12, 24), // Christmas Eve on a Monday
+            ymd(2023, 7, 3),   // Independence Day on a Tuesday
+            ymd(2024, 7, 3),   // Independence Day on a Thursday
+            ymd(2025, 7, 3),   // Independence Day on a Friday
+        ];
+
+        for date in early {
+            assert!(is_trading_day(date), "{date} should be open");
+            assert!(is_early_close(date), "{date} should close early");
+        }
+
+        let regular = [
+            ymd(2026, 7, 2),   // Independence Day on a Saturday
+            ymd(2027, 7, 2),   // Independence Day on a Sunday
+            ymd(2022, 7, 1),   // Independence Day on a Monday
+            ymd(2026, 12, 23),
+            ymd(2026, 11, 25),
+        ];
+
+        for date in regular {
+            assert!(is_trading_day(date), "{date} should be open");
+            assert!(!is_early_close(date), "{date} should close regularly");
+        }
+    }
+
+    #[test]
+    fn early_close_sessions() {
+        let at = |hour, minute| New_York.with_ymd_and_hms(2026, 11, 27, hour, minute, 0).unwrap().with_timezone(&Utc);
+
+        assert_eq!(session(AssetClass::Stock, at(12, 59)), MarketSession::Regular);
+        assert_eq!(session(AssetClass::Stock, at(13, 0)), MarketSession::AfterHours);
+        assert_eq!(session(AssetClass::Stock, at(17, 0)), MarketSession::Closed);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use futures_util::future::join_all;
+use rand::Rng;
+use tokio::{sync::{RwLock, broadcast}, task::JoinHandle, time::sleep};
+use utils::log::{error, info, warn};
+
+use crate::{catch_up_quotes, filter::TickFilter, pipeline::{BatchSettings, TradeSender, spawn_batcher}, providers::{MarketDataProvider, ProviderKind}, types::{FinanceState, PipelineContext, SubscriptionCommand}, websocket::run_connection};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_mins(5);
+
+/// A connection that stayed up this long is considered recovered and resets the backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+const SHARD_COMMAND_CAPACITY: usize = 64;
+
+/// Exponential backoff with equal jitter, so providers that drop at the same
+/// moment do not all come back at the same moment.
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { attempt: 0 }
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let ceiling = INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(self.attempt)).min(MAX_BACKOFF);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = ceiling / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Symbols carried by one of a provider's sockets, shared with the loop that keeps it connected.
+#[derive(Clone)]
+pub(crate) struct Shard {
+    pub id: usize,
+    pub symbols: Arc<RwLock<Vec<String>>>,
+    /// Changes to `symbols`, forwarded as frames on the shard's open socket.
+    pub commands: broadcast::Sender<SubscriptionCommand>,
+    /// Task writing to the open socket, it is spawned apart from the connection loop and has to be stopped on its own.
+    pub writer: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl Shard {
+    pub fn stop_writer(&self) {
+        if let Some(writer) = self.writer.lock().expect("Shard writer lock poisoned").take() {
+            writer.abort();
+        }
+    }
+}
+
+struct ShardHandle {
+    shard: Shard,
+    task: JoinHandle<()>,
+}
+
+/// A provider's sockets, each carrying at most `limit` symbols.
+struct ShardPool {
+    state: FinanceState,
+    provider: Arc<dyn MarketDataProvider>,
+    context: PipelineContext,
+    trades: TradeSender,
+    limit: Option<usize>,
+    shards: Vec<ShardHandle>,
+    next_id: usize,
+}
+
+impl ShardPool {
+    fn shards_needed(&self, symbols: usize) -> usize {
+        self.limit.map_or(1, |limit| symbols.div_ceil(limit)).max(1)
+    }
+
+    /// Splits the initial symbols evenly over as few shards as the limit allows.
+    fn open(&mut self, symbols: Vec<String>) {
+        let per_shard = symbols.len().div_ceil(self.shards_needed(symbols.len())).max(1);
+
+        for chunk in symbols.chunks(per_shard) {
+            self.open_shard(chunk.to_vec());
+        }
+
+        if self.shards.is_empty() {
+            self.open_shard(Vec::new());
+        }
+    }
+
+    fn open_shard(&mut self, symbols: Vec<String>) {
+        let shard = Shard {
+            id: self.next_id,
+            symbols: Arc::new(RwLock::new(symbols)),
+            commands: broadcast::channel(SHARD_COMMAND_CAPACITY).0,
+            writer: Arc::new(Mutex::new(None)),
+        };
+        self.next_id += 1;
+
+        let task = tokio::spawn(maintain_connection(
+            self.state.clone(),
+            Arc::clone(&self.provider),
+            self.context.clone(),
+            self.trades.clone(),
+            shard.clone(),
+        ));
+
+        self.shards.push(ShardHandle { shard, task });
+    }
+
+    /// Drops the shard's socket and returns the symbols it carried.
+    async fn close_shard(&mut self, index: usize) -> Vec<String> {
+        let mut handle = self.shards.remove(index);
+
+        // Wait for the abort so the shard cannot report itself connected after being removed.
+        handle.task.abort();
+        let _ = (&mut handle.task).await;
+        handle.shard.stop_writer();
+
+        self.context.health_state.lock().await.remove_shard(self.provider.kind(), handle.shard.id);
+
+        handle.shard.symbols.read().await.clone()
+    }
+
+    async fn symbol_counts(&self) -> Vec<usize> {
+        let mut counts = Vec::with_capacity(self.shards.len());
+        for handle in &self.shards {
+            counts.push(handle.shard.symbols.read().await.len());
+        }
+
+        counts
+    }
+
+    async fn assigned(&self) -> Vec<String> {
+        let mut symbols = Vec::new();
+        for handle in &self.shards {
+            symbols.extend(handle.shard.symbols.read().await.iter().cloned());
+        }
+
+        symbols
+    }
+
+    async fn shard_of(&self, symbol: &str) -> Option<usize> {
+        for (index, handle) in self.shards.iter().enumerate() {
+            if handle.shard.symbols.read().await.iter().any(|s| s == symbol) {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+
+    /// Puts the symbol on the least loaded shard with room, or on a new one if all are full.
+    async fn add(&mut self, symbol: String) {
+        if self.shard_of(&symbol).await.is_some() {
+            return;
+        }
+
+        let target = self.symbol_counts().await
+            .into_iter()
+            .enumerate()
+            .filter(|(_, count)| self.limit.is_none_or(|limit| *count < limit))
+            .min_by_key(|(_, count)| *count)
+            .map(|(index, _)| index);
+
+        match target {
+            Some(index) => {
+                let shard = &self.shards[index].shard;
+                shard.symbols.write().await.push(symbol.clone());
+
+                // Without receivers the shard is reconnecting and subscribes from `symbols`.
+                let _ = shard.commands.send(SubscriptionCommand::Subscribe(symbol));
+            }
+            None => {
+                info!("Every {} websocket carries {} symbols, opening another for {symbol}", self.provider.kind(), self.limit.unwrap_or_default());
+                self.open_shard(vec![symbol]);
+            }
+        }
+    }
+
+    async fn remove(&mut self, symbol: &str) {
+        let Some(index) = self.shard_of(symbol).await else {
+            return;
+        };
+
+        let shard = &self.shards[index].shard;
+        shard.symbols.write().await.retain(|s| s != symbol);
+        let _ = shard.commands.send(SubscriptionCommand::Unsubscribe(symbol.to_string()));
+
+        self.rebalance().await;
+    }
+
+    /// Closes the least loaded shards for as long as the others can take over their symbols.
+    async fn rebalance(&mut self) {
+        loop {
+            let counts = self.symbol_counts().await;
+            if counts.len() <= self.shards_needed(counts.iter().sum()) {
+                return;
+            }
+
+            let Some((index, _)) = counts.iter().enumerate().min_by_key(|(_, count)| **count) else {
+                return;
+            };
+
+            let symbols = self.close_shard(index).await;
+            info!("Closed a {} websocket, moving its {} symbols to the remaining {}", self.provider.kind(), symbols.len(), self.shards.len());
+
+            for symbol in symbols {
+                self.add(symbol).await;
+            }
+        }
+    }
+
+    /// Applies whatever differs from `wanted`, used when subscription changes were missed.
+    async fn sync(&mut self, wanted: Vec<String>) {
+        for symbol in self.assigned().await {
+            if !wanted.contains(&symbol) {
+                self.remove(&symbol).await;
+            }
+        }
+
+        for symbol in wanted {
+            self.add(symbol).await;
+        }
+    }
+}
+
+async fn provider_symbols(state: &FinanceState, kind: ProviderKind) -> Vec<String> {
+    state.current_subscriptions().await
+        .into_iter()
+        .filter(|symbol| state.providers.kind_for(symbol) == kind)
+        .collect()
+}
+
+/// Keeps a provider's symbols streaming for the lifetime of the service.
+///
+/// The symbols are split over as many sockets as the provider's per-connection
+/// limit requires. An added symbol goes to the least loaded socket, or to a new
+/// one once all are full, and a socket is closed as soon as the others can
+/// carry its symbols. Every socket feeds the provider's one batcher.
+pub(crate) async fn maintain_connections(state: FinanceState, provider: Arc<dyn MarketDataProvider>, context: PipelineContext) {
+    let kind = provider.kind();
+
+    // Listen for changes before taking the snapshot so nothing added in between is missed.
+    let mut commands = state.subscription_commands.subscribe();
+    let symbols = provider_symbols(&state, kind).await;
+
+    let mut pool = ShardPool {
+        trades: spawn_batcher(TickFilter::new(provider.ineligible_conditions()), BatchSettings::from_env(), context.clone()),
+        limit: provider.max_symbols_per_connection(),
+        state: state.clone(),
+        provider,
+        context,
+        shards: Vec::new(),
+        next_id: 0,
+    };
+
+    pool.open(symbols);
+    info!("Streaming {kind} over {} websocket(s), {} symbols per connection at most", pool.shards.len(), pool.limit.map_or("unlimited".to_string(), |limit| limit.to_string()));
+
+    loop {
+        match commands.recv().await {
+            Ok(SubscriptionCommand::Subscribe(symbol)) if state.providers.kind_for(&symbol) == kind => pool.add(symbol).await,
+            Ok(SubscriptionCommand::Unsubscribe(symbol)) if state.providers.kind_for(&symbol) == kind => pool.remove(&symbol).await,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Missed {skipped} subscription changes, resyncing the {kind} websockets");
+                pool.sync(provider_symbols(&state, kind).await).await;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+
+    join_all(pool.shards.into_iter().map(|handle| handle.task)).await;
+}
+
+/// Keeps one shard's socket alive until the pool closes it.
+///
+/// Every (re)connect subscribes to the shard's current symbols, and after a
+/// drop they are re-quoted so moves missed while disconnected are picked up
+/// without waiting for the next trade.
+async fn maintain_connection(state: FinanceState, provider: Arc<dyn MarketDataProvider>, context: PipelineContext, trades: TradeSender, shard: Shard) {
+    let kind = provider.kind();
+    let mut backoff = Backoff::new();
+    let mut has_connected = false;
+
+    loop {
+        match provider.open_stream().await {
+            Ok(streams) => {
+                let connected_at = Instant::now();
+
+                {
+                    let mut health = context.health_state.lock().await;
+                    health.set_connected(kind, shard.id, true);
+                    if has_connected {
+                        health.record_reconnect(kind);
+                    }
+                }
+
+                if has_connected && kind != ProviderKind::Replay {
+                    tokio::spawn(catch_up_quotes(state.clone(), kind, shard.symbols.read().await.clone()));
+                }
+                has_connected = true;
+
+                run_connection(&shard, trades.clone(), Arc::clone(&provider), context.clone(), streams).await;
+                context.health_state.lock().await.set_connected(kind, shard.id, false);
+
+                if connected_at.elapsed() >= STABLE_CONNECTION {
+                    backoff.reset();
+                }
+            }
+            Err(e) => error!("Failed to connect to {kind} websocket {}: {e}", shard.id),
+        }
+
+        let delay = backoff.next_delay();
+        info!("Reconnecting to {kind} websocket {} in {:.1}s...", shard.id, delay.as_secs_f64());
+        sleep(delay).await;
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, env};
+
+use utils::{database::finance::Decimal, log::warn};
+
+use crate::types::{RejectReason, TradeData};
+
+const MAX_DEVIATION_VAR: &str = "FINANCE_TICK_MAX_DEVIATION_PERCENT";
+const WINDOW_VAR: &str = "FINANCE_TICK_WINDOW";
+
+const DEFAULT_MAX_DEVIATION_PERCENT: i64 = 10;
+const DEFAULT_WINDOW: usize = 25;
+
+/// Fewer recent prices than this are not enough to tell an outlier apart.
+const MIN_SAMPLES: usize = 5;
+/// Prices older than this no longer describe the market, so a symbol that
+/// was quiet for a while is not judged against where it traded before.
+const MAX_SAMPLE_AGE_MS: u64 = 15 * 60 * 1000;
+
+/// Recent eligible prices of one symbol.
+#[derive(Default)]
+struct SymbolTicks {
+    /// Timestamp and price, rejected outliers included, so a real jump moves the
+    /// median within half a window instead of being rejected for good.
+    recent: VecDeque<(u64, Decimal)>,
+    last_accepted_at: u64,
+}
+
+impl SymbolTicks {
+    fn median(&self) -> Option<Decimal> {
+        if self.recent.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let mut prices: Vec<Decimal> = self.recent.iter().map(|(_, price)| *price).collect();
+        prices.sort();
+
+        let middle = prices.len() / 2;
+        if prices.len().is_multiple_of(2) {
+            Some((prices[middle - 1] + prices[middle]) / Decimal::TWO)
+        } else {
+            Some(prices[middle])
+        }
+    }
+}
+
+/// Decides which trades of a provider may move the displayed price.
+///
+/// Trades are dropped when they carry a condition the provider marks as not
+/// updating the last price, are older than a trade that was already accepted,
+/// or deviate from the median of the symbol's recent prices by more than
+/// `FINANCE_TICK_MAX_DEVIATION_PERCENT`.
+pub(crate) struct TickFilter {
+    ineligible_conditions: HashSet<String>,
+    max_deviation: Decimal,
+    window: usize,
+    symbols: HashMap<String, SymbolTicks>,
+}
+
+impl TickFilter {
+    pub fn new(ineligible_conditions: Vec<String>) -> Self {
+        let max_deviation = match env::var(MAX_DEVIATION_VAR) {
+            Ok(percent) => percent.parse::<Decimal>().ok().filter(|p| *p > Decimal::ZERO).unwrap_or_else(|| {
+                warn!("Invalid {MAX_DEVIATION_VAR} {percent}, using {DEFAULT_MAX_DEVIATION_PERCENT}");
+                Decimal::from(DEFAULT_MAX_DEVIATION_PERCENT)
+            }),
+            Err(_) => Decimal::from(DEFAULT_MAX_DEVIATION_PERCENT),
+        };
+
+        let window = env::var(WINDOW_VAR).ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|w| *w >= MIN_SAMPLES)
+            .unwrap_or(DEFAULT_WINDOW);
+
+        Self {
+            ineligible_conditions: ineligible_conditions.into_iter().collect(),
+            max_deviation: max_deviation / Decimal::ONE_HUNDRED,
+            window,
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Returns why the trade must not be processed, if it must not.
+    pub fn check(&mut self, trade: &TradeData) -> Option<RejectReason> {
+        if trade.conditions.iter().any(|c| self.ineligible_conditions.contains(c)) {
+            return Some(RejectReason::Condition);
+        }
+
+        let ticks = self.symbols.entry(trade.symbol.clone()).or_default();
+
+        if trade.timestamp < ticks.last_accepted_at {
+            warn!("Rejected {} at {}: traded at {} but {} was already accepted", trade.symbol, trade.price, trade.timestamp, ticks.last_accepted_at);
+            return Some(RejectReason::OutOfSequence);
+        }
+
+        let cutoff = trade.timestamp.saturating_sub(MAX_SAMPLE_AGE_MS);
+        while ticks.recent.front().is_some_and(|(at, _)| *at < cutoff) {
+            ticks.recent.pop_front();
+        }
+
+        let median = ticks.median();
+
+        ticks.recent.push_back((trade.timestamp, trade.price));
+        if ticks.recent.len() > self.window {
+            ticks.recent.pop_front();
+        }
+
+        if let Some(median) = median && median > Decimal::ZERO {
+            let deviation = (trade.price - median).abs() / median;
+
+            if deviation > self.max_deviation {
+                warn!("Rejected {} at {}: {}% away from the recent median {}", trade.symbol, trade.price, (deviation * Decimal::ONE_HUNDRED).round_dp(2), median);
+                return Some(RejectReason::Outlier);
+            }
+        }
+
+        ticks.last_accepted_at = trade.timestamp;
+        None
+    }
+}
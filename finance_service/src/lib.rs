@@ -1,42 +1,115 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use futures_util::future::join_all;
-use reqwest::Client;
-use tokio::{sync::Mutex, time::{self, sleep}};
-use utils::{database::{PgPool, finance::{create_tables, insert_symbol, update_previous_close, update_trade}}, log::{debug, error, info, warn}};
+use futures_util::{StreamExt, future::join_all, stream};
+use tokio::{sync::Mutex, time};
+use utils::{database::{finance::{Decimal, NaiveDate, Utc, create_tables, get_subscriptions, insert_subscription, insert_symbol}, alerts::create_tables as create_alert_tables, news::create_tables as create_news_tables, portfolios::{create_tables as create_portfolio_tables, get_portfolio_symbols}, watchlists::{create_tables as create_watchlist_tables, get_watchlist_symbols}}, log::{debug, info, warn}};
 
-use crate::{types::{FinanceHealth, FinanceState, QuoteResponse}, websocket::connect};
+use crate::{calendar::CryptoReference, connection::maintain_connections, news::STARTUP_NEWS_SYMBOLS, providers::ProviderKind, recorder::FrameRecorder, types::{AssetClass, FinanceHealth, FinanceState, PipelineContext, PriceUpdateSender}};
+
+pub use crate::{metadata::refresh_symbol_metadata, news::refresh_news, subscriptions::{add_subscription, ensure_capacity, normalize_symbol, remove_subscription, sync_subscriptions}};
 
 pub mod types;
+pub mod providers;
+pub mod calendar;
+pub mod alerts;
+pub mod movers;
+pub mod book;
+pub mod portfolio;
+mod connection;
+mod filter;
+mod metadata;
+mod news;
+mod pipeline;
+mod recorder;
+mod subscriptions;
 mod websocket;
 
+const QUOTE_CONCURRENCY: usize = 10;
+
 /// Broadly starts all finance related services and initialization.
-pub async fn start_finance_services(pool: Arc<PgPool>, health_state: Arc<Mutex<FinanceHealth>>) {
+pub async fn start_finance_services(state: FinanceState, health_state: Arc<Mutex<FinanceHealth>>, price_updates: PriceUpdateSender) {
     info!("Starting finance service...");
     // Initialization
-    let state = FinanceState::new(Arc::clone(&pool));
     info!("Creating finance tables...");
-    create_tables(pool.clone()).await;
+    create_tables(state.pool.clone()).await;
+    create_watchlist_tables(state.pool.clone()).await;
+    create_alert_tables(state.pool.clone()).await;
+    create_news_tables(state.pool.clone()).await;
+    create_portfolio_tables(state.pool.clone()).await;
+    load_subscriptions(&state).await;
     initialize_symbols(state.clone()).await;
+    state.book.load().await;
+    if state.providers.is_replay() {
+        info!("Replayed prices are kept in memory only, the trades tables are left untouched");
+    } else {
+        tokio::spawn({
+            let book = Arc::clone(&state.book);
+            let health_state = Arc::clone(&health_state);
+            async move { book.persist(health_state).await }
+        });
+    }
     update_all_previous_closes(state.clone()).await;
+    tokio::spawn(refresh_symbol_metadata(state.clone(), None));
+    tokio::spawn(refresh_news(state.clone(), STARTUP_NEWS_SYMBOLS));
+    state.alerts.reload().await;
+
+    let context = PipelineContext {
+        providers: Arc::clone(&state.providers),
+        pool: Arc::clone(&state.pool),
+        book: Arc::clone(&state.book),
+        health_state,
+        price_updates,
+        recorder: FrameRecorder::from_env(),
+        alerts: Arc::clone(&state.alerts),
+        movers: Arc::clone(&state.movers),
+        crypto_reference: CryptoReference::from_env(),
+    };
+
+    let connections: Vec<_> = state.providers.all().into_iter().map(|provider| {
+        maintain_connections(state.clone(), provider, context.clone())
+    }).collect();
+
+    join_all(connections).await;
+}
+
+/// The database holds the authoritative symbol list once it exists,
+/// `subscriptions.json` is only used to seed it on first start.
+/// Symbols on any watchlist or portfolio are live on top of it.
+async fn load_subscriptions(state: &FinanceState) {
+    match get_subscriptions(state.pool.clone()).await {
+        Ok(stored) if stored.is_empty() => {
+            info!("No stored subscriptions, seeding from configs/subscriptions.json");
 
-    let should_reconnect = true;
+            for symbol in state.current_subscriptions().await {
+                insert_subscription(state.pool.clone(), symbol).await;
+            }
+        }
+        Ok(stored) => {
+            info!("Loaded {} subscriptions from the database", stored.len());
+            *state.subscriptions.write().await = stored;
+        }
+        Err(_) => warn!("Could not read the stored subscriptions, starting with configs/subscriptions.json"),
+    }
 
-    while should_reconnect {
-        connect(state.subscriptions.clone(), state.api_key.clone(), state.client.clone(), pool.clone(), health_state.clone()).await;
+    let referenced = get_watchlist_symbols(state.pool.clone()).await.unwrap_or_default()
+        .into_iter()
+        .chain(get_portfolio_symbols(state.pool.clone()).await.unwrap_or_default());
 
-        error!("Lost websocket, attempting reconnect in 5 minutes...");
-        sleep(Duration::from_mins(5)).await;
+    let mut subscriptions = state.subscriptions.write().await;
+    for symbol in referenced {
+        if !subscriptions.contains(&symbol) {
+            subscriptions.push(symbol);
+        }
     }
 }
 
-/// Initializes a pre-selected set of Finnhub symbols
+/// Initializes the subscribed set of symbols
 /// within the database.
 async fn initialize_symbols(state: FinanceState) {
     info!("Initializing symbols in database...");
 
     let batch_size = 5;
-    for batch in state.subscriptions.chunks(batch_size) {
+    for batch in state.current_subscriptions().await.chunks(batch_size) {
         time::sleep(Duration::from_millis(100)).await;
 
         let futures: Vec<_> = batch.iter().map(|symbol| {
@@ -47,62 +120,92 @@ async fn initialize_symbols(state: FinanceState) {
                 insert_symbol(pool, symbol_clone.clone()).await;
             }
         }).collect();
-        
+
         join_all(futures).await;
     }
 
-    info!("[ Finnhub ] Symbol initialization complete")
+    info!("Symbol initialization complete")
 }
 
-/// Intended to be run once daily via a HTTP request from Supabase.
+/// Intended to be run once daily by the backend's scheduler.
 /// This will also be run once at startup, to populate the database
 /// with a as up-to-date information as is possible.
+///
+/// Symbols whose previous close already belongs to the current trading day
+/// are skipped, so a restart on a weekend or holiday keeps the reference price.
 pub async fn update_all_previous_closes(state: FinanceState) {
     info!("Updating previous closes...");
 
-    let batch_size = 3;
+    let now = Utc::now();
+    let session_dates: HashMap<String, Option<NaiveDate>> = state.book.get_all().await
+        .into_iter()
+        .map(|trade| (trade.symbol, trade.session_date))
+        .collect();
+
+    let symbols: Vec<String> = state.current_subscriptions().await
+        .into_iter()
+        .filter(|symbol| {
+            let trading_day = calendar::trading_day(AssetClass::from_symbol(symbol), now);
+            session_dates.get(symbol).copied().flatten() != Some(trading_day)
+        })
+        .collect();
+
+    info!("{} symbols need a previous close for the current trading day", symbols.len());
+
+    refresh_quotes(&state, symbols).await;
+    state.movers.recompute().await;
+    info!("Previous closes update complete.");
+}
 
-    for batch in state.subscriptions.chunks(batch_size) {
-        time::sleep(Duration::from_millis(1_500)).await;
-        let futures: Vec<_> = batch.iter().map(|symbol| {
-            let client = state.client.clone();
-            let pool = &state.pool;
-            async move {
-                let quote_response = get_quote(symbol.to_string(), client).await;
-
-                match quote_response {
-                    Ok(quote) => {
-
-                        update_previous_close(pool.clone(), symbol.to_string(), quote.previous_close).await;
-
-                        debug!("{symbol} previous close update: {}", quote.previous_close);
-
-                        if quote.change > 0.0 || quote.change < 0.0 {
-                            let direction = if quote.change >= 0.0 {
-                                "up"
-                            } else {
-                                "down"
-                            };
-                            update_trade(pool.clone(), symbol.to_string(), quote.current_price, quote.change, quote.percent_change, direction).await;
-                        }
-                    }
-                    Err(e) => warn!("[ Finnhub ] Quote Error for {}: {e}", symbol),
-                }
-                
-            }
-        }).collect();
+/// Re-quotes the symbols of a reconnected `kind` websocket, to catch
+/// up on moves that happened while the stream was down.
+async fn catch_up_quotes(state: FinanceState, kind: ProviderKind, symbols: Vec<String>) {
+    info!("Catching up {} {kind} quotes after reconnect...", symbols.len());
 
-        join_all(futures).await;
-    }
-    info!("[ Finnhub ] Previous closes update complete.");
+    refresh_quotes(&state, symbols).await;
+    info!("{kind} quote catch-up complete.");
 }
 
-/// Primary way through which the Finnhub HTTP API is accessed.
-async fn get_quote(symbol: String, client: Arc<Client>) -> anyhow::Result<QuoteResponse> {
-        let request = client.get(format!("https://finnhub.io/api/v1/quote?symbol={}", symbol)).build()?;
+/// Refreshes many quotes at once, pacing is left to each provider's rate limited client.
+async fn refresh_quotes(state: &FinanceState, symbols: Vec<String>) {
+    if state.providers.is_replay() {
+        debug!("Skipping {} quote refreshes, recordings carry no quotes", symbols.len());
+        return;
+    }
 
-        let response = client.execute(request).await?.text().await?;
-        let data: QuoteResponse = serde_json::from_str(&response)?;
+    stream::iter(symbols)
+        .for_each_concurrent(QUOTE_CONCURRENCY, |symbol| async move {
+            refresh_quote(state, &symbol).await;
+        })
+        .await;
+}
 
-        Ok(data)
-}
\ No newline at end of file
+/// Stores the previous close and, if the symbol moved, the latest price from a fresh quote.
+async fn refresh_quote(state: &FinanceState, symbol: &str) {
+    let quote_response = state.providers.get_quote(symbol).await;
+
+    let asset_class = AssetClass::from_symbol(symbol);
+    let trading_day = calendar::trading_day(asset_class, Utc::now());
+
+    match quote_response {
+        Ok(quote) => {
+            let previous_close = quote.reference_close(asset_class, trading_day);
+            state.book.set_previous_close(symbol, previous_close, trading_day).await;
+
+            debug!("{symbol} previous close update for {trading_day}: {previous_close}");
+
+            if previous_close != quote.previous_close {
+                // The quote predates the new trading day, so nothing has moved yet.
+                state.book.set_price(symbol, quote.current_price, Decimal::ZERO, Decimal::ZERO, "up").await;
+            } else if !quote.change.is_zero() {
+                let direction = if quote.change.is_sign_positive() {
+                    "up"
+                } else {
+                    "down"
+                };
+                state.book.set_price(symbol, quote.current_price, quote.change, quote.percent_change, direction).await;
+            }
+        }
+        Err(e) => warn!("[ {} ] Quote Error for {}: {e}", state.providers.kind_for(symbol), symbol),
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use futures_util::{StreamExt, stream};
+use utils::{database::finance::{SymbolMetadata, Utc, upsert_symbol_metadata}, log::{info, warn}};
+
+use crate::{QUOTE_CONCURRENCY, providers::SymbolProfile, types::{AssetClass, FinanceState}};
+
+/// Quote currencies of `BINANCE:` pairs, longest first so `BTCFDUSD` is read as `BTC/FDUSD` rather than `BTCFD/USD`.
+const CRYPTO_QUOTE_CURRENCIES: [&str; 8] = ["FDUSD", "USDT", "USDC", "BUSD", "USD", "EUR", "BTC", "ETH"];
+
+/// Fetches metadata for subscribed symbols that have none yet, or whose
+/// metadata is older than `max_age`. `None` only fills in missing symbols.
+pub async fn refresh_symbol_metadata(state: FinanceState, max_age: Option<Duration>) {
+    let subscriptions = state.current_subscriptions().await;
+
+    let updated_at: HashMap<String, chrono::DateTime<Utc>> = state.book.get_metadata(&subscriptions).await
+        .into_iter()
+        .map(|(symbol, metadata)| (symbol, metadata.updated_at))
+        .collect();
+
+    let now = Utc::now();
+    let symbols: Vec<String> = subscriptions.into_iter()
+        .filter(|symbol| match (updated_at.get(symbol), max_age) {
+            (None, _) => true,
+            (Some(updated_at), Some(max_age)) => now - *updated_at >= max_age,
+            (Some(_), None) => false,
+        })
+        .collect();
+
+    if symbols.is_empty() {
+        return;
+    }
+
+    info!("Refreshing metadata for {} symbols...", symbols.len());
+
+    stream::iter(symbols)
+        .for_each_concurrent(QUOTE_CONCURRENCY, |symbol| {
+            let state = &state;
+            async move { refresh_metadata(state, &symbol).await; }
+        })
+        .await;
+
+    info!("Symbol metadata refresh complete.");
+}
+
+/// Stores the provider's profile of `symbol` and caches it in the price book,
+/// a failed request keeps whatever was stored before.
+pub(crate) async fn refresh_metadata(state: &FinanceState, symbol: &str) {
+    let asset_class = AssetClass::from_symbol(symbol);
+
+    let profile = match state.providers.get_profile(symbol).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => fallback_profile(symbol, asset_class),
+        Err(e) => {
+            warn!("[ {} ] Profile Error for {}: {e}", state.providers.kind_for(symbol), symbol);
+            return;
+        }
+    };
+
+    let metadata = SymbolMetadata {
+        symbol: symbol.to_string(),
+        name: profile.name,
+        logo_url: profile.logo_url,
+        exchange: profile.exchange,
+        currency: profile.currency,
+        asset_class: asset_class.as_str().to_string(),
+        sector: profile.sector,
+        updated_at: Utc::now(),
+    };
+
+    state.book.set_metadata(metadata.clone()).await;
+    upsert_symbol_metadata(state.pool.clone(), metadata).await;
+}
+
+/// What can be told from the symbol itself, e.g. `BINANCE:ETHUSDT` is `ETH/USDT` on Binance.
+fn fallback_profile(symbol: &str, asset_class: AssetClass) -> SymbolProfile {
+    if asset_class != AssetClass::Crypto {
+        return SymbolProfile::default();
+    }
+
+    let Some((exchange, pair)) = symbol.split_once(':') else {
+        return SymbolProfile::default();
+    };
+
+    let split = CRYPTO_QUOTE_CURRENCIES.iter()
+        .find_map(|currency| pair.strip_suffix(currency).filter(|base| !base.is_empty()).map(|base| (base, *currency)));
+
+    SymbolProfile {
+        name: Some(split.map_or_else(|| pair.to_string(), |(base, quote)| format!("{base}/{quote}"))),
+        exchange: Some(exchange.to_string()),
+        currency: split.map(|(_, quote)| quote.to_string()),
+        ..Default::default()
+    }
+}
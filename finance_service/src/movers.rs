@@ -0,0 +1,233 @@
+use std::{fs, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utils::{database::finance::{DatabaseTradeData, Decimal}, log::warn};
+
+use crate::{book::PriceBook, types::AssetClass};
+
+const BASKETS_CONFIG_PATH: &str = "./configs/baskets.json";
+
+/// Length of the gainers, losers and most active lists.
+const TOP_MOVERS: usize = 10;
+const PERCENTAGE_SCALE: u32 = 4;
+
+/// Percentage of the symbols that moved that must share a direction for a bullish or bearish mood.
+const MOOD_MAJORITY_PERCENT: usize = 60;
+
+/// A named group of symbols from `configs/baskets.json`, tracked as an equal-weighted index.
+#[derive(Debug, Clone, Deserialize)]
+struct Basket {
+    name: String,
+    symbols: Vec<String>,
+}
+
+fn load_baskets() -> Vec<Basket> {
+    match fs::read_to_string(BASKETS_CONFIG_PATH) {
+        Ok(contents) => {
+            let baskets: Vec<Basket> = serde_json::from_str(&contents).expect("Failed parsing basket configs as Json");
+
+            baskets.into_iter().map(|basket| Basket {
+                symbols: basket.symbols.iter().map(|symbol| symbol.trim().to_uppercase()).collect(),
+                ..basket
+            }).collect()
+        }
+        Err(e) => {
+            warn!("Could not read {BASKETS_CONFIG_PATH} ({e}), no basket indices are computed");
+            Vec::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarketMood {
+    Bullish,
+    Mixed,
+    Bearish,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mover {
+    pub symbol: String,
+    pub asset_class: AssetClass,
+    pub price: Decimal,
+    pub price_change: Decimal,
+    pub percentage_change: Decimal,
+    pub price_scale: i16,
+    pub day_volume: Decimal,
+    /// `day_volume` valued at the current price, so stocks and crypto pairs rank on one scale.
+    pub dollar_volume: Decimal,
+}
+
+impl From<&DatabaseTradeData> for Mover {
+    fn from(trade: &DatabaseTradeData) -> Self {
+        Self {
+            symbol: trade.symbol.clone(),
+            asset_class: AssetClass::from_symbol(&trade.symbol),
+            price: trade.price,
+            price_change: trade.price_change,
+            percentage_change: trade.percentage_change,
+            price_scale: trade.price_scale,
+            day_volume: trade.day_volume,
+            dollar_volume: trade.day_volume * trade.price,
+        }
+    }
+}
+
+/// Advance / decline counts over a set of symbols.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Breadth {
+    pub advancing: usize,
+    pub declining: usize,
+    pub unchanged: usize,
+    /// Equal-weighted mean of the percentage changes.
+    pub average_change: Decimal,
+}
+
+impl Breadth {
+    fn of<'a>(trades: impl Iterator<Item = &'a DatabaseTradeData>) -> Self {
+        let mut breadth = Breadth::default();
+        let mut total_change = Decimal::ZERO;
+
+        for trade in trades {
+            match trade.percentage_change {
+                change if change > Decimal::ZERO => breadth.advancing += 1,
+                change if change < Decimal::ZERO => breadth.declining += 1,
+                _ => breadth.unchanged += 1,
+            }
+
+            total_change += trade.percentage_change;
+        }
+
+        let count = breadth.advancing + breadth.declining + breadth.unchanged;
+        if count > 0 {
+            breadth.average_change = (total_change / Decimal::from(count)).round_dp(PERCENTAGE_SCALE);
+        }
+
+        breadth
+    }
+
+    fn mood(&self) -> MarketMood {
+        let moved = self.advancing + self.declining;
+
+        if moved == 0 {
+            MarketMood::Mixed
+        } else if self.advancing * 100 >= moved * MOOD_MAJORITY_PERCENT {
+            MarketMood::Bullish
+        } else if self.declining * 100 >= moved * MOOD_MAJORITY_PERCENT {
+            MarketMood::Bearish
+        } else {
+            MarketMood::Mixed
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BasketIndex {
+    pub name: String,
+    /// The basket's change is `breadth.average_change`, over the members that have a price.
+    pub breadth: Breadth,
+    /// Members without a price, e.g. because they are not subscribed.
+    pub missing: Vec<String>,
+}
+
+/// Market mood across every tracked symbol.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketSummary {
+    pub computed_at: DateTime<Utc>,
+    pub mood: MarketMood,
+    pub breadth: Breadth,
+    pub top_gainers: Vec<Mover>,
+    pub top_losers: Vec<Mover>,
+    pub most_active: Vec<Mover>,
+    pub baskets: Vec<BasketIndex>,
+}
+
+/// Keeps the latest [`MarketSummary`], recomputed from the price book after every batch.
+pub struct MarketMovers {
+    book: Arc<PriceBook>,
+    baskets: Vec<Basket>,
+    summary: RwLock<Option<Arc<MarketSummary>>>,
+}
+
+impl MarketMovers {
+    pub fn new(book: Arc<PriceBook>) -> Self {
+        Self {
+            book,
+            baskets: load_baskets(),
+            summary: RwLock::new(None),
+        }
+    }
+
+    /// The latest summary, computed now if there is none yet.
+    pub async fn summary(&self) -> Arc<MarketSummary> {
+        if let Some(summary) = self.summary.read().await.as_ref() {
+            return Arc::clone(summary);
+        }
+
+        self.recompute().await
+    }
+
+    pub async fn recompute(&self) -> Arc<MarketSummary> {
+        let trades: Vec<DatabaseTradeData> = self.book.get_all().await
+            .into_iter()
+            .filter(|trade| trade.price > Decimal::ZERO)
+            .collect();
+
+        let summary = Arc::new(self.summarize(&trades));
+        *self.summary.write().await = Some(Arc::clone(&summary));
+
+        summary
+    }
+
+    fn summarize(&self, trades: &[DatabaseTradeData]) -> MarketSummary {
+        let breadth = Breadth::of(trades.iter());
+
+        let mut by_change: Vec<&DatabaseTradeData> = trades.iter().collect();
+        by_change.sort_by_key(|trade| std::cmp::Reverse(trade.percentage_change));
+
+        let top_gainers = by_change.iter()
+            .take_while(|trade| trade.percentage_change > Decimal::ZERO)
+            .take(TOP_MOVERS)
+            .map(|trade| Mover::from(*trade))
+            .collect();
+
+        let top_losers = by_change.iter().rev()
+            .take_while(|trade| trade.percentage_change < Decimal::ZERO)
+            .take(TOP_MOVERS)
+            .map(|trade| Mover::from(*trade))
+            .collect();
+
+        let mut most_active: Vec<Mover> = trades.iter()
+            .filter(|trade| trade.day_volume > Decimal::ZERO)
+            .map(Mover::from)
+            .collect();
+        most_active.sort_by_key(|mover| std::cmp::Reverse(mover.dollar_volume));
+        most_active.truncate(TOP_MOVERS);
+
+        let baskets = self.baskets.iter().map(|basket| {
+            let members: Vec<&DatabaseTradeData> = trades.iter().filter(|trade| basket.symbols.contains(&trade.symbol)).collect();
+
+            BasketIndex {
+                name: basket.name.clone(),
+                breadth: Breadth::of(members.iter().copied()),
+                missing: basket.symbols.iter()
+                    .filter(|symbol| !members.iter().any(|trade| &trade.symbol == *symbol))
+                    .cloned()
+                    .collect(),
+            }
+        }).collect();
+
+        MarketSummary {
+            computed_at: Utc::now(),
+            mood: breadth.mood(),
+            breadth,
+            top_gainers,
+            top_losers,
+            most_active,
+            baskets,
+        }
+    }
+}
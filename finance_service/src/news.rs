@@ -0,0 +1,78 @@
+use std::sync::atomic::Ordering;
+
+use chrono::{Days, Utc};
+use futures_util::{StreamExt, stream};
+use utils::{database::news::{NewsHeadline, insert_news}, log::{info, warn}};
+
+use crate::{providers::NewsArticle, types::{AssetClass, FinanceState}};
+
+/// Company news are requested for this many days back, older articles were stored by earlier runs.
+const COMPANY_NEWS_LOOKBACK_DAYS: u64 = 2;
+
+/// Company news requested by the refresh at startup.
+pub(crate) const STARTUP_NEWS_SYMBOLS: usize = 10;
+
+/// Stores the latest general market headlines and the company news of the next
+/// `symbols_per_run` subscribed stocks, picking up where the previous run stopped.
+/// Headlines that are already stored are skipped.
+///
+/// News share the rate limited clients with quotes, so the requests go out one
+/// at a time and quotes queued in between are not held up by a whole run.
+pub async fn refresh_news(state: FinanceState, symbols_per_run: usize) {
+    info!("Refreshing news...");
+
+    let mut stored = match state.providers.get_market_news().await {
+        Ok(articles) => insert_news(state.pool.clone(), headlines(articles), None).await,
+        Err(e) => {
+            warn!("Market news error: {e}");
+            0
+        }
+    };
+
+    let to = Utc::now().date_naive();
+    let from = to - Days::new(COMPANY_NEWS_LOOKBACK_DAYS);
+
+    let stocks: Vec<String> = state.current_subscriptions().await
+        .into_iter()
+        .filter(|symbol| AssetClass::from_symbol(symbol) == AssetClass::Stock)
+        .collect();
+
+    let count = symbols_per_run.min(stocks.len());
+    let start = state.news_cursor.fetch_add(count, Ordering::Relaxed);
+    let symbols: Vec<String> = stocks.iter().cycle().skip(start % stocks.len().max(1)).take(count).cloned().collect();
+
+    stored += stream::iter(symbols)
+        .then(|symbol| {
+            let state = &state;
+            async move {
+                match state.providers.get_company_news(&symbol, from, to).await {
+                    Ok(articles) => insert_news(state.pool.clone(), headlines(articles), Some(symbol)).await,
+                    Err(e) => {
+                        warn!("[ {} ] News Error for {}: {e}", state.providers.kind_for(&symbol), symbol);
+                        0
+                    }
+                }
+            }
+        })
+        .collect::<Vec<u64>>()
+        .await
+        .into_iter()
+        .sum::<u64>();
+
+    info!("News refresh complete, {stored} new headlines.");
+}
+
+fn headlines(articles: Vec<NewsArticle>) -> Vec<NewsHeadline> {
+    articles.into_iter().map(|article| NewsHeadline {
+        id: 0,
+        provider: article.provider.as_str().to_string(),
+        provider_id: article.id,
+        headline: article.headline,
+        summary: article.summary,
+        source: article.source,
+        url: article.url,
+        image_url: article.image_url,
+        published_at: article.published_at,
+        symbols: Vec::new(),
+    }).collect()
+}
@@ -0,0 +1,503 @@
+use std::{collections::{HashMap, HashSet, hash_map::Entry}, env, future::pending, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use futures_util::{StreamExt, stream};
+use tokio::{sync::{Mutex, mpsc}, time};
+use utils::{database::{PgPool, finance::{DatabaseTradeData, Decimal, NaiveDate, TradeBatchRow, Utc, get_prices_at}}, log::{error, info, warn}};
+
+use crate::{calendar::{self, CryptoReference}, filter::TickFilter, providers::ProviderRegistry, types::{AssetClass, BatchStats, PipelineContext, PriceBatch, PriceUpdate, QueuedTrade, RejectReason, TradeData}};
+
+const BATCH_SIZE_VAR: &str = "FINANCE_BATCH_SIZE";
+const BATCH_MAX_LATENCY_VAR: &str = "FINANCE_BATCH_MAX_LATENCY_MS";
+const BATCH_CHANNEL_CAPACITY_VAR: &str = "FINANCE_BATCH_CHANNEL_CAPACITY";
+
+const DEFAULT_BATCH_SIZE: usize = 50;
+const DEFAULT_BATCH_MAX_LATENCY_MS: usize = 1000;
+const DEFAULT_BATCH_CHANNEL_CAPACITY: usize = 256;
+
+const LOG_THROTTLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A crypto tick this much older than the reference moment says nothing about the price at it.
+const MAX_REFERENCE_AGE_HOURS: i64 = 24;
+
+const MIN_PRICE_SCALE: i16 = 2;
+const PERCENTAGE_SCALE: u32 = 4;
+
+/// When a batch is processed and how far the connections may run ahead of it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BatchSettings {
+    /// Queued symbols that are processed right away, without waiting for `max_latency`.
+    pub size: usize,
+    /// Longest a queued trade waits for its batch.
+    pub max_latency: Duration,
+    /// Trade messages buffered between the connections and the batcher. Once
+    /// it is full the connections stop reading until the batcher catches up.
+    pub channel_capacity: usize,
+}
+
+impl BatchSettings {
+    pub fn from_env() -> Self {
+        let positive = |var: &str, default: usize| match env::var(var) {
+            Ok(value) if !value.trim().is_empty() => value.trim().parse::<usize>().ok().filter(|v| *v > 0).unwrap_or_else(|| {
+                warn!("Invalid {var} {value}, using {default}");
+                default
+            }),
+            _ => default,
+        };
+
+        Self {
+            size: positive(BATCH_SIZE_VAR, DEFAULT_BATCH_SIZE),
+            max_latency: Duration::from_millis(positive(BATCH_MAX_LATENCY_VAR, DEFAULT_BATCH_MAX_LATENCY_MS) as u64),
+            channel_capacity: positive(BATCH_CHANNEL_CAPACITY_VAR, DEFAULT_BATCH_CHANNEL_CAPACITY),
+        }
+    }
+}
+
+/// Where a provider's connections send the trades they parse.
+pub(crate) type TradeSender = mpsc::Sender<Vec<TradeData>>;
+
+/// Starts the batcher of one provider and returns the sender its connections feed.
+pub(crate) fn spawn_batcher(tick_filter: TickFilter, settings: BatchSettings, context: PipelineContext) -> TradeSender {
+    let (sender, receiver) = mpsc::channel(settings.channel_capacity);
+
+    let batcher = Batcher {
+        settings,
+        context,
+        tick_filter,
+        queue: HashMap::new(),
+        held: HashMap::new(),
+        rolling_over: Arc::new(Mutex::new(HashSet::new())),
+        deadline: None,
+        stats: BatchStats::default(),
+        last_log_time: None,
+    };
+    tokio::spawn(batcher.run(receiver));
+
+    sender
+}
+
+/// Sole owner of a provider's queued trades.
+///
+/// Trades are filtered and queued as they arrive, and processed once
+/// `size` symbols are queued or the oldest of them waited `max_latency`.
+/// Batches run one after another on this task, so nothing is shared
+/// with the connections besides the channel.
+struct Batcher {
+    settings: BatchSettings,
+    context: PipelineContext,
+    tick_filter: TickFilter,
+    queue: HashMap<String, QueuedTrade>,
+    /// Trades of symbols whose rollover quote is still being fetched, they are processed once it is stored.
+    held: HashMap<String, QueuedTrade>,
+    /// Symbols rolling over to a new trading day on a task of their own.
+    rolling_over: Arc<Mutex<HashSet<String>>>,
+    /// When the queued trades are due, set by the first trade of a batch.
+    deadline: Option<time::Instant>,
+    stats: BatchStats,
+    last_log_time: Option<Instant>,
+}
+
+impl Batcher {
+    async fn run(mut self, mut receiver: mpsc::Receiver<Vec<TradeData>>) {
+        loop {
+            let deadline = self.deadline;
+
+            tokio::select! {
+                received = receiver.recv() => match received {
+                    Some(trades) => {
+                        self.enqueue(trades).await;
+
+                        if self.queue.len() >= self.settings.size {
+                            self.flush().await;
+                        }
+                    }
+                    None => {
+                        // Every connection is gone, what is queued is still worth storing.
+                        self.flush().await;
+                        break;
+                    }
+                },
+
+                _ = async {
+                    match deadline {
+                        Some(deadline) => time::sleep_until(deadline).await,
+                        None => pending().await,
+                    }
+                } => self.flush().await,
+            }
+        }
+    }
+
+    async fn enqueue(&mut self, trades: Vec<TradeData>) {
+        let rejected = queue_trades(&mut self.tick_filter, &mut self.queue, trades);
+
+        if !rejected.is_empty() {
+            for (reason, count) in &rejected {
+                *self.stats.rejected_ticks.entry(*reason).or_default() += count;
+            }
+
+            self.context.health_state.lock().await.record_rejected(&rejected);
+        }
+
+        if self.deadline.is_none() && !self.queue.is_empty() {
+            self.deadline = Some(time::Instant::now() + self.settings.max_latency);
+        }
+    }
+
+    async fn flush(&mut self) {
+        self.deadline = None;
+
+        if self.queue.is_empty() && self.held.is_empty() {
+            return;
+        }
+
+        let mut batch: HashMap<String, QueuedTrade> = HashMap::new();
+        {
+            let rolling_over = self.rolling_over.lock().await;
+            let held = std::mem::take(&mut self.held);
+
+            for (symbol, trade) in self.queue.drain().chain(held) {
+                let target = if rolling_over.contains(&symbol) { &mut self.held } else { &mut batch };
+
+                match target.entry(symbol) {
+                    Entry::Occupied(mut queued_trade) => queued_trade.get_mut().merge(trade),
+                    Entry::Vacant(slot) => {
+                        slot.insert(trade);
+                    }
+                }
+            }
+        }
+
+        // Held trades are looked at again with the next batch, or on their own once the deadline passes.
+        if !self.held.is_empty() {
+            self.deadline = Some(time::Instant::now() + self.settings.max_latency);
+        }
+
+        if batch.is_empty() {
+            return;
+        }
+
+        let trades: Vec<QueuedTrade> = batch.into_values().collect();
+
+        self.stats.batches_processed += 1;
+        let batch_num = self.stats.batches_processed;
+
+        let (processed, errors) = process_batch(trades, batch_num, &self.context, &self.rolling_over).await;
+
+        self.stats.failed_trades += errors;
+        self.stats.total_updates_processed += processed;
+
+        let now = Instant::now();
+        let should_log = self.last_log_time.is_none_or(|last| now.duration_since(last) >= LOG_THROTTLE_INTERVAL);
+
+        if should_log {
+            self.last_log_time = Some(now);
+            info!("Batch #{} complete: {} processed, {} errors", batch_num, processed, errors);
+            info!("Total updates processed: {}", self.stats.total_updates_processed);
+
+            if !self.stats.rejected_ticks.is_empty() {
+                info!("Total ticks rejected: {:?}", self.stats.rejected_ticks);
+            }
+        }
+    }
+}
+
+/// Filters the trades and folds the rest into the queue. Returns how many were rejected and why.
+pub(crate) fn queue_trades(tick_filter: &mut TickFilter, queue: &mut HashMap<String, QueuedTrade>, trades: Vec<TradeData>) -> HashMap<RejectReason, u64> {
+    let mut rejected: HashMap<RejectReason, u64> = HashMap::new();
+
+    for trade in trades {
+        if let Some(reason) = tick_filter.check(&trade) {
+            *rejected.entry(reason).or_default() += 1;
+            continue;
+        }
+
+        // Only the latest trade per symbol is processed, the others still count towards its range and volume.
+        match queue.entry(trade.symbol.clone()) {
+            Entry::Occupied(mut queued_trade) => queued_trade.get_mut().add(trade),
+            Entry::Vacant(slot) => {
+                slot.insert(QueuedTrade::new(trade));
+            }
+        }
+    }
+
+    rejected
+}
+
+/// Prices, stores and publishes one batch. Returns how many trades were processed and how many failed.
+///
+/// Trades that roll over to a new trading day wait on a provider quote, they are
+/// handed to a task of their own so the batcher keeps draining the connections.
+async fn process_batch(trades: Vec<QueuedTrade>, batch_num: u64, context: &PipelineContext, rolling_over: &Arc<Mutex<HashSet<String>>>) -> (u64, u64) {
+    let PipelineContext { providers, pool, book, crypto_reference, .. } = context;
+
+    info!("Processing batch #{} with {} trades", batch_num, trades.len());
+
+    let symbols: Vec<String> = trades.iter().map(|t| t.latest.symbol.clone()).collect();
+    let crypto_symbols = symbols.iter().filter(|s| AssetClass::from_symbol(s) == AssetClass::Crypto).cloned().collect();
+
+    let trades_map = Arc::new(book.get_many(&symbols).await);
+    let references = crypto_reference_closes(Arc::clone(pool), *crypto_reference, crypto_symbols).await;
+
+    let now = Utc::now();
+    let (rollovers, trades): (Vec<QueuedTrade>, Vec<QueuedTrade>) = trades.into_iter().partition(|trade| {
+        let symbol = &trade.latest.symbol;
+        let trading_day = calendar::trading_day(AssetClass::from_symbol(symbol), now);
+
+        references.get(symbol).is_none_or(|close| *close <= Decimal::ZERO) && needs_quote(trades_map.get(symbol), trading_day)
+    });
+
+    if !rollovers.is_empty() {
+        info!("Rolling over {} symbols of batch #{} in the background", rollovers.len(), batch_num);
+
+        let mut in_flight = rolling_over.lock().await;
+        in_flight.extend(rollovers.iter().map(|trade| trade.latest.symbol.clone()));
+
+        tokio::spawn(roll_over(rollovers, batch_num, context.clone(), Arc::clone(&trades_map), Arc::clone(rolling_over)));
+    }
+
+    let (accepted, processed, errors) = price_trades(trades, trades_map, Arc::clone(providers), &references).await;
+    publish(accepted, batch_num, errors, context).await;
+
+    (processed, errors)
+}
+
+/// Prices trades that need a fresh quote for their previous close, then releases their symbols.
+async fn roll_over(trades: Vec<QueuedTrade>, batch_num: u64, context: PipelineContext, trades_map: Arc<HashMap<String, DatabaseTradeData>>, rolling_over: Arc<Mutex<HashSet<String>>>) {
+    let symbols: Vec<String> = trades.iter().map(|t| t.latest.symbol.clone()).collect();
+
+    let (accepted, _, errors) = price_trades(trades, trades_map, Arc::clone(&context.providers), &HashMap::new()).await;
+    publish(accepted, batch_num, errors, &context).await;
+
+    let mut in_flight = rolling_over.lock().await;
+    for symbol in &symbols {
+        in_flight.remove(symbol);
+    }
+}
+
+/// Whether the stored previous close belongs to an earlier trading day, or there is none yet.
+fn needs_quote(record: Option<&DatabaseTradeData>, trading_day: NaiveDate) -> bool {
+    record.is_none_or(|record| record.previous_close <= Decimal::ZERO || record.session_date != Some(trading_day))
+}
+
+/// Works out the rows and updates of many trades, returns them with the processed and failed counts.
+async fn price_trades(trades: Vec<QueuedTrade>, trades_map: Arc<HashMap<String, DatabaseTradeData>>, providers: Arc<ProviderRegistry>, references: &HashMap<String, Decimal>) -> (Vec<(TradeBatchRow, PriceUpdate)>, u64, u64) {
+    let processed_count = Arc::new(AtomicU64::new(0));
+    let error_count = Arc::new(AtomicU64::new(0));
+    let accepted_updates = Arc::new(Mutex::new(Vec::new()));
+
+    let batch_size = 5;
+
+    stream::iter(trades)
+        .for_each_concurrent(batch_size, |trade| {
+            let trades_map_clone = Arc::clone(&trades_map);
+            let proc_clone = Arc::clone(&processed_count);
+            let err_clone = Arc::clone(&error_count);
+            let providers_clone = Arc::clone(&providers);
+            let updates_clone = Arc::clone(&accepted_updates);
+            let reference_close = references.get(&trade.latest.symbol).copied();
+
+            async move {
+                match process_single_trade(trade, trades_map_clone, providers_clone, reference_close).await {
+                    Ok(accepted) => {
+                        proc_clone.fetch_add(1, Ordering::SeqCst);
+
+                        if let Some(accepted) = accepted {
+                            updates_clone.lock().await.push(accepted);
+                        }
+                    }
+                    Err(e) => {
+                        err_clone.fetch_add(1, Ordering::SeqCst);
+                        warn!("Error processing trade: {}", e);
+                    }
+                }
+            }
+        }
+    ).await;
+
+    let accepted = std::mem::take(&mut *accepted_updates.lock().await);
+    (accepted, processed_count.load(Ordering::SeqCst), error_count.load(Ordering::SeqCst))
+}
+
+/// Applies priced trades to the book and hands them to alerts, movers and the streams.
+async fn publish(accepted: Vec<(TradeBatchRow, PriceUpdate)>, batch_num: u64, errors: u64, context: &PipelineContext) {
+    let PipelineContext { book, health_state, price_updates, alerts, movers, .. } = context;
+
+    let (rows, updates): (Vec<TradeBatchRow>, Vec<PriceUpdate>) = accepted.into_iter().unzip();
+
+    // The book serves the new prices right away, the database catches up in the background.
+    book.apply_batch(rows).await;
+
+    health_state.lock().await.record_batch(batch_num, errors, &updates);
+
+    alerts.evaluate(&updates).await;
+
+    if !updates.is_empty() {
+        movers.recompute().await;
+
+        // Sending only fails when nobody is listening, which is not an error.
+        let _ = price_updates.send(Arc::new(PriceBatch { batch: batch_num, updates }));
+    }
+}
+
+/// Crypto has no close to roll over from, so its changes are measured against our own
+/// tick history and match what the exchange the trades come from shows.
+async fn crypto_reference_closes(pool: Arc<PgPool>, reference: CryptoReference, symbols: Vec<String>) -> HashMap<String, Decimal> {
+    if symbols.is_empty() {
+        return HashMap::new();
+    }
+
+    let at = reference.reference_time(Utc::now());
+    let not_before = at - chrono::Duration::hours(MAX_REFERENCE_AGE_HOURS);
+
+    get_prices_at(pool, symbols, at, not_before).await.into_iter().collect()
+}
+
+/// Works out the new price row and stream update for a trade, the batch writes all rows at once.
+///
+/// `reference_close` replaces the stored previous close when given, it is only known for crypto.
+async fn process_single_trade(trade: QueuedTrade, trades_map: Arc<HashMap<String, DatabaseTradeData>>, providers: Arc<ProviderRegistry>, reference_close: Option<Decimal>) -> anyhow::Result<Option<(TradeBatchRow, PriceUpdate)>> {
+    let QueuedTrade { latest, open, high, low, volume, trade_count, .. } = trade;
+    let (symbol, price, conditions) = (latest.symbol, latest.price, latest.conditions);
+    let traded_at = chrono::DateTime::from_timestamp_millis(latest.timestamp as i64).unwrap_or_else(Utc::now);
+
+    let existing_record = trades_map.get(&symbol).cloned();
+    let mut current_record = existing_record.unwrap_or_else(|| {
+        info!("Inserting new symbol {}", symbol);
+
+        DatabaseTradeData {
+            symbol: symbol.clone(),
+            price,
+            previous_close: Decimal::ZERO,
+            price_change: Decimal::ZERO,
+            percentage_change: Decimal::ZERO,
+            direction: String::from("up"),
+            last_updated: Utc::now(),
+            session_date: None,
+            price_scale: MIN_PRICE_SCALE,
+            day_open: None,
+            day_high: None,
+            day_low: None,
+            day_volume: Decimal::ZERO,
+        }
+    });
+
+    let asset_class = AssetClass::from_symbol(&symbol);
+    let trading_day = calendar::trading_day(asset_class, Utc::now());
+
+    // Mirrors `PriceBook::apply_batch`, a new trading day starts the range over.
+    let (day_open, day_high, day_low, day_volume) = if current_record.session_date == Some(trading_day) {
+        (
+            current_record.day_open.unwrap_or(open),
+            current_record.day_high.map_or(high, |day_high| day_high.max(high)),
+            current_record.day_low.map_or(low, |day_low| day_low.min(low)),
+            current_record.day_volume + volume,
+        )
+    } else {
+        (open, high, low, volume)
+    };
+
+    // A previous close from an earlier trading day means the session rolled over since it was stored,
+    // crypto skips that as soon as its history covers the reference moment.
+    if let Some(reference_close) = reference_close && reference_close > Decimal::ZERO {
+        current_record.previous_close = reference_close;
+    } else if current_record.previous_close <= Decimal::ZERO || current_record.session_date != Some(trading_day) {
+        let mut determined_previous_close: Option<Decimal> = None;
+
+        // Recordings carry no quotes, replayed symbols roll over from their prices.
+        if !providers.is_replay() {
+            info!("Fetching quote for {} ({})", symbol, trading_day);
+
+            match providers.get_quote(&symbol).await {
+                Ok(quote) => {
+                    let reference_close = quote.reference_close(asset_class, trading_day);
+
+                    if reference_close > Decimal::ZERO {
+                        determined_previous_close = Some(reference_close);
+                    } else if quote.current_price > Decimal::ZERO {
+                        determined_previous_close = Some(quote.current_price);
+                    }
+                }
+
+                Err(e) => {
+                    error!("Quote API error for {}: {}", symbol, e);
+                }
+            }
+        }
+
+        if determined_previous_close.is_none() && current_record.price > Decimal::ZERO && current_record.session_date.is_some() {
+            warn!("Quote unavailable for {}, rolling over from the last stored price", symbol);
+            determined_previous_close = Some(current_record.price);
+        }
+
+        if determined_previous_close.is_none() {
+            warn!("Quote unavailable for {}, using live price fallback", symbol);
+            determined_previous_close = Some(price);
+        }
+
+        if let Some(pc) = determined_previous_close {
+            current_record.previous_close = pc;
+        }
+    }
+
+    if current_record.previous_close <= Decimal::ZERO {
+        warn!("Skipping {}, unable to determine previous close", symbol);
+        return Ok(None);
+    }
+
+    let previous_close = current_record.previous_close;
+    let current_price = price;
+
+    if current_price <= Decimal::ZERO {
+        warn!("Invalid prices for {}: current={}", symbol, current_price);
+        return Ok(None);
+    }
+
+    let price_change = current_price - previous_close;
+    let percentage_change = if previous_close.is_zero() {
+        Decimal::ZERO
+    } else {
+        (price_change / previous_close * Decimal::ONE_HUNDRED).round_dp(PERCENTAGE_SCALE)
+    };
+
+    let direction = if price_change >= Decimal::ZERO { "up" } else { "down" };
+
+    // Precision only ever grows, a single round price must not hide a symbol's sub-cent moves.
+    let price_scale = (current_price.normalize().scale() as i16).max(current_record.price_scale).max(MIN_PRICE_SCALE);
+
+    let row = TradeBatchRow {
+        symbol: symbol.clone(),
+        price: current_price,
+        previous_close,
+        price_change,
+        percentage_change,
+        direction: direction.to_string(),
+        session_date: trading_day,
+        price_scale,
+        open,
+        high,
+        low,
+        volume,
+        trade_count,
+        traded_at,
+    };
+
+    let update = PriceUpdate {
+        symbol,
+        price: current_price,
+        previous_close,
+        price_change,
+        percentage_change,
+        direction: direction.to_string(),
+        volume,
+        traded_at,
+        session: calendar::session(asset_class, traded_at),
+        price_scale,
+        conditions,
+        day_open,
+        day_high,
+        day_low,
+        day_volume,
+    };
+
+    Ok(Some((row, update)))
+}
\ No newline at end of file
@@ -0,0 +1,121 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utils::database::{finance::Decimal, portfolios::{Holding, PortfolioLot}};
+
+use crate::{book::PriceBook, types::{AssetClass, PriceBatch}};
+
+const PERCENTAGE_SCALE: u32 = 4;
+
+/// Value and profit or loss at the current price.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Performance {
+    pub market_value: Decimal,
+    /// Change in market value since the previous close.
+    pub day_change: Decimal,
+    pub day_change_percent: Decimal,
+    pub unrealized_pl: Decimal,
+    pub unrealized_pl_percent: Decimal,
+}
+
+impl Performance {
+    fn new(market_value: Decimal, day_change: Decimal, cost_basis: Decimal) -> Self {
+        let unrealized_pl = market_value - cost_basis;
+
+        Self {
+            market_value,
+            day_change,
+            day_change_percent: percent_of(day_change, market_value - day_change),
+            unrealized_pl,
+            unrealized_pl_percent: percent_of(unrealized_pl, cost_basis),
+        }
+    }
+}
+
+fn percent_of(change: Decimal, base: Decimal) -> Decimal {
+    if base.is_zero() {
+        Decimal::ZERO
+    } else {
+        (change / base * Decimal::ONE_HUNDRED).round_dp(PERCENTAGE_SCALE)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HoldingValue {
+    pub symbol: String,
+    pub asset_class: AssetClass,
+    pub quantity: Decimal,
+    pub cost_basis: Decimal,
+    pub average_cost: Decimal,
+    pub lots: Vec<PortfolioLot>,
+    pub price: Option<Decimal>,
+    pub price_scale: Option<i16>,
+    /// `None` until the symbol has traded, such a holding is left out of the totals.
+    pub performance: Option<Performance>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioValue {
+    pub computed_at: DateTime<Utc>,
+    pub holdings: Vec<HoldingValue>,
+    /// Cost of the holdings that have a price.
+    pub cost_basis: Decimal,
+    pub performance: Performance,
+    /// Held symbols without a price yet.
+    pub unpriced: Vec<String>,
+}
+
+/// Values the holdings at the latest prices in the book.
+pub async fn value_portfolio(book: &PriceBook, holdings: Vec<Holding>) -> PortfolioValue {
+    let symbols: Vec<String> = holdings.iter().map(|holding| holding.symbol.clone()).collect();
+    let prices = book.get_many(&symbols).await;
+
+    let mut values = Vec::with_capacity(holdings.len());
+    let mut unpriced = Vec::new();
+    let (mut cost_basis, mut market_value, mut day_change) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+
+    for holding in holdings {
+        let quantity = holding.quantity();
+        let holding_cost = holding.cost_basis();
+        let trade = prices.get(&holding.symbol).filter(|trade| trade.price > Decimal::ZERO);
+
+        let performance = trade.map(|trade| {
+            let value = quantity * trade.price;
+            let change = if trade.previous_close > Decimal::ZERO { quantity * trade.price_change } else { Decimal::ZERO };
+
+            cost_basis += holding_cost;
+            market_value += value;
+            day_change += change;
+
+            Performance::new(value, change, holding_cost)
+        });
+
+        if performance.is_none() {
+            unpriced.push(holding.symbol.clone());
+        }
+
+        values.push(HoldingValue {
+            asset_class: AssetClass::from_symbol(&holding.symbol),
+            average_cost: if quantity.is_zero() { Decimal::ZERO } else { holding_cost / quantity },
+            price: trade.map(|trade| trade.price),
+            price_scale: trade.map(|trade| trade.price_scale),
+            symbol: holding.symbol,
+            quantity,
+            cost_basis: holding_cost,
+            lots: holding.lots,
+            performance,
+        });
+    }
+
+    PortfolioValue {
+        computed_at: Utc::now(),
+        holdings: values,
+        cost_basis,
+        performance: Performance::new(market_value, day_change, cost_basis),
+        unpriced,
+    }
+}
+
+/// Whether the batch moved any of the holdings.
+pub fn batch_touches(holdings: &[Holding], batch: &PriceBatch) -> bool {
+    batch.updates.iter().any(|update| holdings.iter().any(|holding| holding.symbol == update.symbol))
+}
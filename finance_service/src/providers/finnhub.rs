@@ -0,0 +1,226 @@
+use std::{env, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate};
+use reqwest::{Client, header::{HeaderMap, HeaderValue}};
+use serde::Deserialize;
+use utils::database::finance::Decimal;
+
+use crate::{providers::{HttpMetrics, MarketDataProvider, NewsArticle, condition_codes, symbols_per_connection, RateLimitedClient, ProviderKind, Quote, StreamMessage, SymbolProfile}, types::{AssetClass, TradeData, exact_decimal}};
+
+const INELIGIBLE_CONDITIONS_VAR: &str = "FINNHUB_INELIGIBLE_CONDITIONS";
+const SYMBOLS_PER_CONNECTION_VAR: &str = "FINNHUB_SYMBOLS_PER_CONNECTION";
+
+/// Finnhub stops streaming symbols beyond this many on a single socket.
+const DEFAULT_SYMBOLS_PER_CONNECTION: usize = 50;
+
+/// Finnhub's condition codes differ per exchange, so none are dropped unless configured.
+pub(crate) fn ineligible_conditions() -> Vec<String> {
+    condition_codes(INELIGIBLE_CONDITIONS_VAR, &[])
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeUpdate {
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    data: Vec<TradeData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuoteResponse {
+    #[serde(rename = "c", deserialize_with = "exact_decimal")]
+    current_price: Decimal,
+    #[serde(rename = "d", deserialize_with = "exact_decimal")]
+    change: Decimal,
+    #[serde(rename = "dp", deserialize_with = "exact_decimal")]
+    percent_change: Decimal,
+    #[serde(rename = "pc", deserialize_with = "exact_decimal")]
+    previous_close: Decimal,
+    #[serde(rename = "t", default)]
+    timestamp: i64,
+}
+
+/// `/stock/profile2` answers `{}` for symbols it has no profile for.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ProfileResponse {
+    name: String,
+    logo: String,
+    exchange: String,
+    currency: String,
+    finnhub_industry: String,
+}
+
+/// An article of `/news` and `/company-news`, `datetime` is in unix seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NewsResponse {
+    id: i64,
+    datetime: i64,
+    headline: String,
+    summary: String,
+    source: String,
+    url: String,
+    image: String,
+}
+
+impl NewsResponse {
+    fn into_article(self) -> Option<NewsArticle> {
+        if self.headline.is_empty() || self.url.is_empty() {
+            return None;
+        }
+
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+
+        Some(NewsArticle {
+            provider: ProviderKind::Finnhub,
+            id: self.id.to_string(),
+            headline: self.headline,
+            summary: non_empty(self.summary),
+            source: non_empty(self.source),
+            url: self.url,
+            image_url: non_empty(self.image),
+            published_at: DateTime::from_timestamp(self.datetime, 0)?,
+        })
+    }
+}
+
+pub(crate) fn parse_message(text: &str) -> Vec<StreamMessage> {
+    let message = match serde_json::from_str::<TradeUpdate>(text) {
+        Ok(update) => match update.message_type.as_str() {
+            "trade" => StreamMessage::Trades(update.data),
+            "ping" => StreamMessage::Ignored,
+            "error" => StreamMessage::Error(text.to_string()),
+            _ => StreamMessage::Unexpected(text.to_string()),
+        },
+        Err(_) if text.contains("error") => StreamMessage::Error(text.to_string()),
+        Err(_) => StreamMessage::Unexpected(text.to_string()),
+    };
+
+    vec![message]
+}
+
+pub(crate) struct FinnhubProvider {
+    api_key: String,
+    client: RateLimitedClient,
+}
+
+impl FinnhubProvider {
+    pub fn new() -> Self {
+        let api_key = env::var("FINNHUB_API_KEY").expect("Finnhub API key needs to be set in .env");
+
+        let mut headers: HeaderMap = HeaderMap::new();
+        headers.append("X-Finnhub-Token", HeaderValue::from_str(&api_key).expect("Failed casting api_key to HeaderValue"));
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_millis(10_000))
+            .build().expect("Failed creating finance Reqwest Client");
+
+        // The free tier allows 60 calls a minute.
+        let client = RateLimitedClient::new("Finnhub", client, "FINNHUB_CALLS_PER_MINUTE", 60);
+
+        Self { api_key, client }
+    }
+
+    async fn get_news(&self, url: String) -> Result<Vec<NewsArticle>> {
+        let request = self.client.client().get(url).build()?;
+
+        let response = self.client.execute(request).await?.text().await?;
+        let data: Vec<NewsResponse> = serde_json::from_str(&response)?;
+
+        Ok(data.into_iter().filter_map(NewsResponse::into_article).collect())
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for FinnhubProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Finnhub
+    }
+
+    fn stream_url(&self) -> String {
+        format!("wss://ws.finnhub.io/?token={}", self.api_key)
+    }
+
+    fn subscribe_message(&self, symbol: &str) -> String {
+        format!(r#"{{"type":"subscribe","symbol":"{}"}}"#, symbol)
+    }
+
+    fn unsubscribe_message(&self, symbol: &str) -> String {
+        format!(r#"{{"type":"unsubscribe","symbol":"{}"}}"#, symbol)
+    }
+
+    fn parse_message(&self, text: &str) -> Vec<StreamMessage> {
+        parse_message(text)
+    }
+
+    /// Primary way through which the Finnhub HTTP API is accessed.
+    async fn get_quote(&self, symbol: &str) -> Result<Quote> {
+        let request = self.client.client().get(format!("https://finnhub.io/api/v1/quote?symbol={}", symbol)).build()?;
+
+        let response = self.client.execute(request).await?.text().await?;
+        let data: QuoteResponse = serde_json::from_str(&response)?;
+
+        Ok(Quote {
+            current_price: data.current_price,
+            change: data.change,
+            percent_change: data.percent_change,
+            previous_close: data.previous_close,
+            as_of: DateTime::from_timestamp(data.timestamp, 0).filter(|_| data.timestamp > 0),
+        })
+    }
+
+    /// Company profiles only cover stocks, crypto pairs have none.
+    async fn get_profile(&self, symbol: &str) -> Result<Option<SymbolProfile>> {
+        if AssetClass::from_symbol(symbol) == AssetClass::Crypto {
+            return Ok(None);
+        }
+
+        let request = self.client.client().get(format!("https://finnhub.io/api/v1/stock/profile2?symbol={}", symbol)).build()?;
+
+        let response = self.client.execute(request).await?.text().await?;
+        let data: ProfileResponse = serde_json::from_str(&response)?;
+
+        if data.name.is_empty() {
+            return Ok(None);
+        }
+
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+
+        Ok(Some(SymbolProfile {
+            name: Some(data.name),
+            logo_url: non_empty(data.logo),
+            exchange: non_empty(data.exchange),
+            currency: non_empty(data.currency),
+            sector: non_empty(data.finnhub_industry),
+        }))
+    }
+
+    async fn get_market_news(&self) -> Result<Vec<NewsArticle>> {
+        self.get_news("https://finnhub.io/api/v1/news?category=general".to_string()).await
+    }
+
+    /// Company news only cover stocks, crypto headlines are part of the general news.
+    async fn get_company_news(&self, symbol: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<NewsArticle>> {
+        if AssetClass::from_symbol(symbol) == AssetClass::Crypto {
+            return Ok(Vec::new());
+        }
+
+        self.get_news(format!("https://finnhub.io/api/v1/company-news?symbol={}&from={}&to={}", symbol, from, to)).await
+    }
+
+    fn ineligible_conditions(&self) -> Vec<String> {
+        ineligible_conditions()
+    }
+
+    fn max_symbols_per_connection(&self) -> Option<usize> {
+        symbols_per_connection(SYMBOLS_PER_CONNECTION_VAR, Some(DEFAULT_SYMBOLS_PER_CONNECTION))
+    }
+
+    fn http_metrics(&self) -> Option<HttpMetrics> {
+        Some(self.client.metrics())
+    }
+}
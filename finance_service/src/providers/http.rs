@@ -0,0 +1,172 @@
+use std::{env, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, Request, Response, StatusCode, header::RETRY_AFTER};
+use serde::Serialize;
+use tokio::{sync::Mutex, time::{Instant, sleep}};
+use utils::log::warn;
+
+const MAX_RETRIES: u32 = 3;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_BURST: u32 = 10;
+
+/// Counters for a provider's HTTP API, reported by `/finance/health`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HttpMetrics {
+    pub requests: u64,
+    /// Calls that had to wait for the rate limit budget.
+    pub throttled: u64,
+    /// `429` responses from the provider, which should stay at zero.
+    pub rate_limited: u64,
+    pub retries: u64,
+    pub failures: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    throttled: AtomicU64,
+    rate_limited: AtomicU64,
+    retries: AtomicU64,
+    failures: AtomicU64,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket refilled continuously at `per_minute`, holding at most `burst` tokens.
+///
+/// Waiters sleep while holding the (FIFO) lock, so callers are served in the order they arrived.
+struct TokenBucket {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    fn new(per_minute: u32) -> Self {
+        let burst = per_minute.clamp(1, MAX_BURST) as f64;
+
+        Self {
+            per_second: per_minute.max(1) as f64 / 60.0,
+            burst,
+            bucket: Mutex::new(Bucket { tokens: burst, refilled_at: Instant::now() }),
+        }
+    }
+
+    /// Takes a token, returns whether the caller had to wait for it.
+    async fn acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().await;
+
+        let now = Instant::now();
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * self.per_second).min(self.burst);
+        bucket.refilled_at = now;
+
+        let throttled = bucket.tokens < 1.0;
+        if throttled {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second);
+            sleep(wait).await;
+
+            bucket.tokens = 1.0;
+            bucket.refilled_at = Instant::now();
+        }
+
+        bucket.tokens -= 1.0;
+        throttled
+    }
+}
+
+/// The single path to a provider's HTTP API, so no burst of callers can exceed its budget.
+pub(crate) struct RateLimitedClient {
+    name: &'static str,
+    client: Client,
+    limiter: TokenBucket,
+    counters: Counters,
+}
+
+impl RateLimitedClient {
+    /// `per_minute_var` overrides `default_per_minute` from the environment.
+    pub fn new(name: &'static str, client: Client, per_minute_var: &str, default_per_minute: u32) -> Self {
+        let per_minute = env::var(per_minute_var).ok().and_then(|v| v.parse().ok()).unwrap_or(default_per_minute);
+
+        Self {
+            name,
+            client,
+            limiter: TokenBucket::new(per_minute),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Sends the request, retrying `429`, `5xx` and connection errors with exponential backoff.
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        for attempt in 0..=MAX_RETRIES {
+            if attempt > 0 {
+                self.counters.retries.fetch_add(1, Ordering::Relaxed);
+                sleep(delay).await;
+                delay *= 2;
+            }
+
+            if self.limiter.acquire().await {
+                self.counters.throttled.fetch_add(1, Ordering::Relaxed);
+            }
+            self.counters.requests.fetch_add(1, Ordering::Relaxed);
+
+            let attempt_request = request.try_clone().context("Only requests without streaming bodies can be retried")?;
+
+            let response = match self.client.execute(attempt_request).await {
+                Ok(response) => response,
+                Err(e) if e.is_timeout() || e.is_connect() => {
+                    warn!("{} request failed ({e}), attempt {} of {}", self.name, attempt + 1, MAX_RETRIES + 1);
+                    continue;
+                }
+                Err(e) => {
+                    self.counters.failures.fetch_add(1, Ordering::Relaxed);
+                    return Err(e.into());
+                }
+            };
+
+            let status = response.status();
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                self.counters.rate_limited.fetch_add(1, Ordering::Relaxed);
+
+                if let Some(retry_after) = retry_after(&response) {
+                    delay = delay.max(retry_after);
+                }
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                warn!("{} responded {status}, attempt {} of {}", self.name, attempt + 1, MAX_RETRIES + 1);
+                continue;
+            }
+
+            return Ok(response);
+        }
+
+        self.counters.failures.fetch_add(1, Ordering::Relaxed);
+        bail!("{} request failed after {} attempts", self.name, MAX_RETRIES + 1)
+    }
+
+    pub fn metrics(&self) -> HttpMetrics {
+        HttpMetrics {
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            throttled: self.counters.throttled.load(Ordering::Relaxed),
+            rate_limited: self.counters.rate_limited.load(Ordering::Relaxed),
+            retries: self.counters.retries.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?.parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
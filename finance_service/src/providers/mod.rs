@@ -0,0 +1,361 @@
+use std::{collections::HashMap, env, fmt::Display, fs, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{Sink, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::{connect_async, tungstenite::{Error as WsError, protocol::Message}};
+use utils::{database::finance::Decimal, log::{info, warn}};
+
+use crate::{calendar, types::{AssetClass, TradeData}};
+
+pub use http::HttpMetrics;
+pub(crate) use http::RateLimitedClient;
+
+pub(crate) mod finnhub;
+mod http;
+pub(crate) mod polygon;
+pub(crate) mod replay;
+
+const PROVIDERS_CONFIG_PATH: &str = "./configs/providers.json";
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Condition codes from a comma separated list in `var`, or `defaults` when it is empty.
+pub(crate) fn condition_codes(var: &str, defaults: &[&str]) -> Vec<String> {
+    match env::var(var) {
+        Ok(codes) if !codes.trim().is_empty() => codes.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect(),
+        _ => defaults.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+/// Symbols per websocket from `var`, `0` lifts the limit and an unset or empty `var` keeps `default`.
+pub(crate) fn symbols_per_connection(var: &str, default: Option<usize>) -> Option<usize> {
+    match env::var(var) {
+        Ok(limit) if !limit.trim().is_empty() => match limit.trim().parse::<usize>() {
+            Ok(0) => None,
+            Ok(limit) => Some(limit),
+            Err(_) => {
+                warn!("Invalid {var} {limit}, using {default:?}");
+                default
+            }
+        },
+        _ => default,
+    }
+}
+
+/// Market-data sources `finance_service` knows how to talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Finnhub,
+    Polygon,
+    Replay,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::Finnhub => "finnhub",
+            ProviderKind::Polygon => "polygon",
+            ProviderKind::Replay => "replay",
+        }
+    }
+}
+
+impl Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderKind::Finnhub => write!(f, "Finnhub"),
+            ProviderKind::Polygon => write!(f, "Polygon"),
+            ProviderKind::Replay => write!(f, "Replay"),
+        }
+    }
+}
+
+/// Provider independent snapshot of a symbol.
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub current_price: Decimal,
+    pub change: Decimal,
+    pub percent_change: Decimal,
+    pub previous_close: Decimal,
+    /// When `current_price` was last updated, if the provider reports it.
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+impl Quote {
+    /// Close that moves on `trading_day` are measured against.
+    ///
+    /// A quote whose price predates the trading day (e.g. Monday pre-market)
+    /// has not rolled over yet, so its current price is that reference.
+    pub fn reference_close(&self, asset_class: AssetClass, trading_day: NaiveDate) -> Decimal {
+        match self.as_of {
+            Some(as_of) if self.current_price > Decimal::ZERO && calendar::exchange_date(asset_class, as_of) < trading_day => self.current_price,
+            _ => self.previous_close,
+        }
+    }
+}
+
+/// Company profile of a symbol, any field the provider does not know is `None`.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolProfile {
+    pub name: Option<String>,
+    pub logo_url: Option<String>,
+    pub exchange: Option<String>,
+    pub currency: Option<String>,
+    pub sector: Option<String>,
+}
+
+/// A headline from the provider's news API.
+#[derive(Debug, Clone)]
+pub struct NewsArticle {
+    pub provider: ProviderKind,
+    /// The provider's own id, only unique per provider.
+    pub id: String,
+    pub headline: String,
+    pub summary: Option<String>,
+    pub source: Option<String>,
+    pub url: String,
+    pub image_url: Option<String>,
+    pub published_at: DateTime<Utc>,
+}
+
+pub(crate) type FrameStream = Pin<Box<dyn Stream<Item = Result<Message, WsError>> + Send>>;
+pub(crate) type FrameSink = Pin<Box<dyn Sink<Message, Error = WsError> + Send>>;
+
+/// A single decoded event from a provider's trade stream.
+#[derive(Debug)]
+pub(crate) enum StreamMessage {
+    Trades(Vec<TradeData>),
+    /// The provider accepted our handshake and subscriptions may now be sent.
+    Ready,
+    Error(String),
+    Unexpected(String),
+    Ignored,
+}
+
+/// A source of streaming trades and on-demand quotes.
+///
+/// The websocket connection and batching pipeline are shared between
+/// providers, so implementations only describe their wire format.
+#[async_trait]
+pub(crate) trait MarketDataProvider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    /// Websocket endpoint of the trade stream.
+    fn stream_url(&self) -> String;
+
+    /// Opens the trade stream, by default a websocket to [`Self::stream_url`].
+    async fn open_stream(&self) -> Result<(FrameSink, FrameStream)> {
+        let (ws_stream, _) = connect_async(self.stream_url()).await?;
+        let (writer, reader) = ws_stream.split();
+
+        Ok((Box::pin(writer), Box::pin(reader)))
+    }
+
+    /// Frames sent right after connecting, before any subscriptions.
+    fn handshake_messages(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// How often to ping the stream, `None` disables the keepalive and the idle timeout.
+    fn keepalive_interval(&self) -> Option<Duration> {
+        Some(DEFAULT_KEEPALIVE_INTERVAL)
+    }
+
+    /// Whether subscriptions must wait for a [`StreamMessage::Ready`] after the handshake.
+    fn waits_for_ready(&self) -> bool {
+        false
+    }
+
+    fn subscribe_message(&self, symbol: &str) -> String;
+
+    fn unsubscribe_message(&self, symbol: &str) -> String;
+
+    fn parse_message(&self, text: &str) -> Vec<StreamMessage>;
+
+    async fn get_quote(&self, symbol: &str) -> Result<Quote>;
+
+    async fn previous_close(&self, symbol: &str) -> Result<Decimal> {
+        Ok(self.get_quote(symbol).await?.previous_close)
+    }
+
+    /// Reference data for the symbol, `None` if the provider has no profile for it.
+    async fn get_profile(&self, _symbol: &str) -> Result<Option<SymbolProfile>> {
+        Ok(None)
+    }
+
+    /// Latest general market headlines.
+    async fn get_market_news(&self) -> Result<Vec<NewsArticle>> {
+        Ok(Vec::new())
+    }
+
+    /// Headlines about `symbol` published between `from` and `to`, both inclusive.
+    async fn get_company_news(&self, _symbol: &str, _from: NaiveDate, _to: NaiveDate) -> Result<Vec<NewsArticle>> {
+        Ok(Vec::new())
+    }
+
+    /// Trade condition codes that must not move the displayed price.
+    fn ineligible_conditions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// How many symbols one websocket may carry, larger sets are split over several connections.
+    fn max_symbols_per_connection(&self) -> Option<usize> {
+        None
+    }
+
+    /// Counters of the provider's rate limited HTTP client, if it has one.
+    fn http_metrics(&self) -> Option<HttpMetrics> {
+        None
+    }
+}
+
+/// Contents of `configs/providers.json`, symbol overrides win over asset class overrides.
+#[derive(Debug, Deserialize)]
+struct ProviderConfig {
+    default: ProviderKind,
+    #[serde(default)]
+    asset_classes: HashMap<AssetClass, ProviderKind>,
+    #[serde(default)]
+    symbols: HashMap<String, ProviderKind>,
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            default: ProviderKind::Finnhub,
+            asset_classes: HashMap::new(),
+            symbols: HashMap::new(),
+        }
+    }
+}
+
+impl ProviderConfig {
+    fn load() -> Result<Self> {
+        let config: Self = match fs::read_to_string(PROVIDERS_CONFIG_PATH) {
+            Ok(contents) => serde_json::from_str(&contents).with_context(|| format!("Failed parsing {PROVIDERS_CONFIG_PATH} as Json"))?,
+            Err(e) => {
+                warn!("Could not read {PROVIDERS_CONFIG_PATH} ({e}), using Finnhub for every symbol");
+                Self::default()
+            }
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The replay provider can not be routed to, it replaces every live provider when selected.
+    fn validate(&self) -> Result<()> {
+        if self.kinds().contains(&ProviderKind::Replay) {
+            bail!("{PROVIDERS_CONFIG_PATH} can not use the replay provider, it is selected with {}", replay::REPLAY_PATH_VAR);
+        }
+
+        Ok(())
+    }
+
+    fn kinds(&self) -> Vec<ProviderKind> {
+        let mut kinds = vec![self.default];
+
+        for kind in self.asset_classes.values().chain(self.symbols.values()) {
+            if !kinds.contains(kind) {
+                kinds.push(*kind);
+            }
+        }
+
+        kinds
+    }
+}
+
+/// Routes every symbol to the provider configured for it.
+pub struct ProviderRegistry {
+    config: ProviderConfig,
+    providers: HashMap<ProviderKind, Arc<dyn MarketDataProvider>>,
+}
+
+impl ProviderRegistry {
+    /// Only constructs the providers the configuration references, so
+    /// credentials are only required for sources that are actually used.
+    pub fn from_config() -> Result<Self> {
+        if let Ok(path) = env::var(replay::REPLAY_PATH_VAR) {
+            info!("Replaying recorded market data from {path}, live providers are disabled");
+
+            let config = ProviderConfig { default: ProviderKind::Replay, ..Default::default() };
+            let provider: Arc<dyn MarketDataProvider> = Arc::new(replay::ReplayProvider::new(path));
+
+            return Ok(Self { config, providers: HashMap::from([(ProviderKind::Replay, provider)]) });
+        }
+
+        let mut config = ProviderConfig::load()?;
+        config.symbols = config.symbols.into_iter().map(|(symbol, kind)| (symbol.to_uppercase(), kind)).collect();
+
+        let providers = config.kinds().into_iter().map(|kind| {
+            let provider: Arc<dyn MarketDataProvider> = match kind {
+                ProviderKind::Finnhub => Arc::new(finnhub::FinnhubProvider::new()),
+                ProviderKind::Polygon => Arc::new(polygon::PolygonProvider::new()),
+                ProviderKind::Replay => unreachable!("Rejected by ProviderConfig::validate"),
+            };
+
+            (kind, provider)
+        }).collect::<HashMap<_, _>>();
+
+        info!("Market data providers in use: {:?}", providers.keys().collect::<Vec<_>>());
+
+        Ok(Self { config, providers })
+    }
+
+    pub fn kind_for(&self, symbol: &str) -> ProviderKind {
+        if let Some(kind) = self.config.symbols.get(symbol) {
+            return *kind;
+        }
+
+        self.config.asset_classes
+            .get(&AssetClass::from_symbol(symbol))
+            .copied()
+            .unwrap_or(self.config.default)
+    }
+
+    pub(crate) fn for_symbol(&self, symbol: &str) -> Arc<dyn MarketDataProvider> {
+        Arc::clone(&self.providers[&self.kind_for(symbol)])
+    }
+
+    /// Whether a recording is streamed instead of the live providers.
+    pub fn is_replay(&self) -> bool {
+        self.providers.contains_key(&ProviderKind::Replay)
+    }
+
+    pub(crate) fn all(&self) -> Vec<Arc<dyn MarketDataProvider>> {
+        self.providers.values().cloned().collect()
+    }
+
+    pub async fn get_quote(&self, symbol: &str) -> Result<Quote> {
+        self.for_symbol(symbol).get_quote(symbol).await
+    }
+
+    pub async fn previous_close(&self, symbol: &str) -> Result<Decimal> {
+        self.for_symbol(symbol).previous_close(symbol).await
+    }
+
+    pub async fn get_profile(&self, symbol: &str) -> Result<Option<SymbolProfile>> {
+        self.for_symbol(symbol).get_profile(symbol).await
+    }
+
+    /// General news come from the default provider.
+    pub async fn get_market_news(&self) -> Result<Vec<NewsArticle>> {
+        match self.providers.get(&self.config.default) {
+            Some(provider) => provider.get_market_news().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub async fn get_company_news(&self, symbol: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<NewsArticle>> {
+        self.for_symbol(symbol).get_company_news(symbol, from, to).await
+    }
+
+    pub fn http_metrics(&self) -> HashMap<ProviderKind, HttpMetrics> {
+        self.providers.iter()
+            .filter_map(|(kind, provider)| Some((*kind, provider.http_metrics()?)))
+            .collect()
+    }
+}
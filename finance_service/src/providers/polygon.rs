@@ -0,0 +1,323 @@
+use std::{env, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use utils::database::finance::Decimal;
+
+use crate::{providers::{HttpMetrics, MarketDataProvider, NewsArticle, condition_codes, symbols_per_connection, RateLimitedClient, ProviderKind, Quote, StreamMessage, SymbolProfile}, types::{TradeData, exact_decimal}};
+
+const DEFAULT_STREAM_URL: &str = "wss://socket.polygon.io/stocks";
+const REST_URL: &str = "https://api.polygon.io";
+
+/// Articles per news request, the API allows up to 1000.
+const NEWS_LIMIT: u32 = 50;
+
+const INELIGIBLE_CONDITIONS_VAR: &str = "POLYGON_INELIGIBLE_CONDITIONS";
+/// Conditions the SIPs do not update the last sale with: average price, cash sale,
+/// derivatively priced, sold out of sequence, next day, price variation,
+/// prior reference price, odd lot and (qualified) contingent trades.
+const DEFAULT_INELIGIBLE_CONDITIONS: &[&str] = &["2", "7", "10", "13", "20", "21", "22", "32", "33", "37", "52", "53"];
+
+const SYMBOLS_PER_CONNECTION_VAR: &str = "POLYGON_SYMBOLS_PER_CONNECTION";
+
+pub(crate) fn ineligible_conditions() -> Vec<String> {
+    condition_codes(INELIGIBLE_CONDITIONS_VAR, DEFAULT_INELIGIBLE_CONDITIONS)
+}
+
+#[derive(Debug, Deserialize)]
+struct EventKind {
+    ev: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeEvent {
+    sym: String,
+    #[serde(deserialize_with = "exact_decimal")]
+    p: Decimal,
+    #[serde(default, deserialize_with = "exact_decimal")]
+    s: Decimal,
+    t: u64,
+    #[serde(default)]
+    c: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusEvent {
+    status: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Debug)]
+enum StreamEvent {
+    Trade(TradeEvent),
+    Status(StatusEvent),
+    Other,
+}
+
+impl StreamEvent {
+    /// The tag is read first, a tagged enum would buffer the event and lose the raw text of its prices.
+    fn parse(raw: &RawValue) -> serde_json::Result<Self> {
+        Ok(match serde_json::from_str::<EventKind>(raw.get())?.ev.as_str() {
+            "T" => StreamEvent::Trade(serde_json::from_str(raw.get())?),
+            "status" => StreamEvent::Status(serde_json::from_str(raw.get())?),
+            _ => StreamEvent::Other,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotResponse {
+    ticker: SnapshotTicker,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotTicker {
+    #[serde(deserialize_with = "exact_decimal")]
+    todays_change: Decimal,
+    #[serde(deserialize_with = "exact_decimal")]
+    todays_change_perc: Decimal,
+    day: SnapshotBar,
+    prev_day: SnapshotBar,
+    last_trade: Option<SnapshotTrade>,
+    /// Nanoseconds since the epoch.
+    #[serde(default)]
+    updated: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotBar {
+    #[serde(deserialize_with = "exact_decimal")]
+    c: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotTrade {
+    #[serde(deserialize_with = "exact_decimal")]
+    p: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviousCloseResponse {
+    #[serde(default)]
+    results: Vec<SnapshotBar>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerDetailsResponse {
+    results: TickerDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerDetails {
+    name: Option<String>,
+    primary_exchange: Option<String>,
+    currency_name: Option<String>,
+    sic_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewsListResponse {
+    #[serde(default)]
+    results: Vec<NewsResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewsResult {
+    id: String,
+    title: String,
+    description: Option<String>,
+    article_url: String,
+    image_url: Option<String>,
+    published_utc: DateTime<Utc>,
+    publisher: Option<Publisher>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Publisher {
+    name: Option<String>,
+}
+
+impl From<NewsResult> for NewsArticle {
+    fn from(result: NewsResult) -> Self {
+        Self {
+            provider: ProviderKind::Polygon,
+            id: result.id,
+            headline: result.title,
+            summary: result.description,
+            source: result.publisher.and_then(|publisher| publisher.name),
+            url: result.article_url,
+            image_url: result.image_url,
+            published_at: result.published_utc,
+        }
+    }
+}
+
+pub(crate) fn parse_message(text: &str) -> Vec<StreamMessage> {
+    let events = serde_json::from_str::<Vec<Box<RawValue>>>(text)
+        .and_then(|events| events.iter().map(|event| StreamEvent::parse(event)).collect::<serde_json::Result<Vec<_>>>());
+
+    let events = match events {
+        Ok(events) => events,
+        Err(_) => return vec![StreamMessage::Unexpected(text.to_string())],
+    };
+
+    let mut trades = Vec::new();
+    let mut messages = Vec::new();
+
+    for event in events {
+        match event {
+            StreamEvent::Trade(TradeEvent { sym, p, s, t, c }) => trades.push(TradeData {
+                symbol: sym,
+                price: p,
+                timestamp: t,
+                volume: s,
+                conditions: c.iter().map(i64::to_string).collect(),
+            }),
+            StreamEvent::Status(StatusEvent { status, message }) => match status.as_str() {
+                "auth_success" => messages.push(StreamMessage::Ready),
+                "auth_failed" | "error" => messages.push(StreamMessage::Error(format!("{status}: {message}"))),
+                _ => messages.push(StreamMessage::Ignored),
+            },
+            StreamEvent::Other => messages.push(StreamMessage::Ignored),
+        }
+    }
+
+    if !trades.is_empty() {
+        messages.push(StreamMessage::Trades(trades));
+    }
+
+    messages
+}
+
+/// Polygon.io stock trades, `BINANCE:` style crypto symbols are not supported.
+pub(crate) struct PolygonProvider {
+    api_key: String,
+    stream_url: String,
+    client: RateLimitedClient,
+}
+
+impl PolygonProvider {
+    pub fn new() -> Self {
+        let api_key = env::var("POLYGON_API_KEY").expect("Polygon API key needs to be set in .env when Polygon is configured");
+        let stream_url = env::var("POLYGON_WEBSOCKET_URL").unwrap_or_else(|_| DEFAULT_STREAM_URL.to_string());
+
+        let client = Client::builder()
+            .timeout(Duration::from_millis(10_000))
+            .build().expect("Failed creating Polygon Reqwest Client");
+
+        // The free tier allows 5 calls a minute.
+        let client = RateLimitedClient::new("Polygon", client, "POLYGON_CALLS_PER_MINUTE", 5);
+
+        Self { api_key, stream_url, client }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T> {
+        let request = self.client.client()
+            .get(format!("{REST_URL}{path}"))
+            .query(&[("apiKey", &self.api_key)])
+            .build()?;
+
+        let response = self.client.execute(request).await?.text().await?;
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for PolygonProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Polygon
+    }
+
+    fn stream_url(&self) -> String {
+        self.stream_url.clone()
+    }
+
+    fn handshake_messages(&self) -> Vec<String> {
+        vec![format!(r#"{{"action":"auth","params":"{}"}}"#, self.api_key)]
+    }
+
+    fn waits_for_ready(&self) -> bool {
+        true
+    }
+
+    fn subscribe_message(&self, symbol: &str) -> String {
+        format!(r#"{{"action":"subscribe","params":"T.{}"}}"#, symbol)
+    }
+
+    fn unsubscribe_message(&self, symbol: &str) -> String {
+        format!(r#"{{"action":"unsubscribe","params":"T.{}"}}"#, symbol)
+    }
+
+    fn parse_message(&self, text: &str) -> Vec<StreamMessage> {
+        parse_message(text)
+    }
+
+    async fn get_quote(&self, symbol: &str) -> Result<Quote> {
+        let snapshot: SnapshotResponse = self.get(&format!("/v2/snapshot/locale/us/markets/stocks/tickers/{symbol}")).await?;
+        let ticker = snapshot.ticker;
+
+        let current_price = ticker.last_trade.map(|trade| trade.p).unwrap_or(ticker.day.c);
+
+        Ok(Quote {
+            current_price,
+            change: ticker.todays_change,
+            percent_change: ticker.todays_change_perc,
+            previous_close: ticker.prev_day.c,
+            as_of: (ticker.updated > 0).then(|| DateTime::from_timestamp_nanos(ticker.updated)),
+        })
+    }
+
+    async fn previous_close(&self, symbol: &str) -> Result<Decimal> {
+        let response: PreviousCloseResponse = self.get(&format!("/v2/aggs/ticker/{symbol}/prev?adjusted=true")).await?;
+
+        response.results
+            .first()
+            .map(|bar| bar.c)
+            .with_context(|| format!("Polygon returned no previous close for {symbol}"))
+    }
+
+    /// Polygon's branding images require the API key to download, so no logo is exposed.
+    async fn get_profile(&self, symbol: &str) -> Result<Option<SymbolProfile>> {
+        let details: TickerDetailsResponse = self.get(&format!("/v3/reference/tickers/{symbol}")).await?;
+        let details = details.results;
+
+        Ok(Some(SymbolProfile {
+            name: details.name,
+            logo_url: None,
+            exchange: details.primary_exchange,
+            currency: details.currency_name.map(|currency| currency.to_uppercase()),
+            sector: details.sic_description,
+        }))
+    }
+
+    async fn get_market_news(&self) -> Result<Vec<NewsArticle>> {
+        let news: NewsListResponse = self.get(&format!("/v2/reference/news?order=desc&limit={NEWS_LIMIT}")).await?;
+        Ok(news.results.into_iter().map(NewsArticle::from).collect())
+    }
+
+    async fn get_company_news(&self, symbol: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<NewsArticle>> {
+        let path = format!("/v2/reference/news?ticker={symbol}&published_utc.gte={from}&published_utc.lte={to}T23:59:59Z&order=desc&limit={NEWS_LIMIT}");
+
+        let news: NewsListResponse = self.get(&path).await?;
+        Ok(news.results.into_iter().map(NewsArticle::from).collect())
+    }
+
+    fn ineligible_conditions(&self) -> Vec<String> {
+        ineligible_conditions()
+    }
+
+    /// Unlimited by default, plans that cap the symbols per socket set `POLYGON_SYMBOLS_PER_CONNECTION`.
+    fn max_symbols_per_connection(&self) -> Option<usize> {
+        symbols_per_connection(SYMBOLS_PER_CONNECTION_VAR, None)
+    }
+
+    fn http_metrics(&self) -> Option<HttpMetrics> {
+        Some(self.client.metrics())
+    }
+}
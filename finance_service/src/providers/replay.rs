@@ -0,0 +1,245 @@
+use std::{env, sync::Mutex, time::Duration};
+
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use futures_util::{SinkExt, stream};
+use tokio::{fs::File, io::{AsyncBufReadExt, BufReader}, time::sleep};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use utils::log::warn;
+
+use crate::{providers::{FrameSink, FrameStream, MarketDataProvider, ProviderKind, Quote, StreamMessage, finnhub, polygon}, recorder::RecordedFrame};
+
+pub(crate) const REPLAY_PATH_VAR: &str = "FINANCE_REPLAY_PATH";
+const REPLAY_SPEED_VAR: &str = "FINANCE_REPLAY_SPEED";
+
+/// Feeds a file written by the frame recorder back through the live pipeline.
+///
+/// Frames are paced by their recorded timestamps divided by `FINANCE_REPLAY_SPEED`,
+/// where `1` is real time and `0` replays as fast as possible. Once the file is
+/// exhausted the stream ends and the regular reconnect loop starts it over, with
+/// the trade timestamps moved past the previous pass so they are not out of sequence.
+pub(crate) struct ReplayProvider {
+    path: String,
+    speed: f64,
+    clock: Mutex<ReplayClock>,
+}
+
+/// Shifts the trade timestamps of each pass over the recording.
+#[derive(Default)]
+struct ReplayClock {
+    offset: u64,
+    latest: u64,
+    /// Set when a pass starts, its first trade decides the new offset.
+    rebase: bool,
+}
+
+impl ReplayClock {
+    fn shift(&mut self, timestamp: u64) -> u64 {
+        if self.rebase {
+            self.rebase = false;
+            self.offset = if self.latest == 0 { 0 } else { (self.latest + 1).saturating_sub(timestamp) };
+        }
+
+        let shifted = timestamp + self.offset;
+        self.latest = self.latest.max(shifted);
+        shifted
+    }
+}
+
+impl ReplayProvider {
+    pub fn new(path: String) -> Self {
+        let speed = match env::var(REPLAY_SPEED_VAR) {
+            Ok(speed) => speed.parse::<f64>().ok().filter(|s| s.is_finite() && *s >= 0.0).unwrap_or_else(|| {
+                warn!("Invalid {REPLAY_SPEED_VAR} {speed}, replaying in real time");
+                1.0
+            }),
+            Err(_) => 1.0,
+        };
+
+        Self { path, speed, clock: Mutex::default() }
+    }
+
+    fn start_pass(&self) {
+        self.clock.lock().expect("Replay clock lock poisoned").rebase = true;
+    }
+}
+
+struct ReplayCursor {
+    lines: tokio::io::Lines<BufReader<File>>,
+    last_at: Option<i64>,
+    speed: f64,
+}
+
+async fn next_frame(mut cursor: ReplayCursor) -> Option<(Result<Message, tokio_tungstenite::tungstenite::Error>, ReplayCursor)> {
+    loop {
+        let line = match cursor.lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return None,
+            Err(e) => return Some((Err(e.into()), cursor)),
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Pacing only needs the timestamp, the frame itself is decoded in `parse_message`.
+        if let Ok(recorded) = serde_json::from_str::<RecordedFrame>(&line) {
+            if let Some(last_at) = cursor.last_at && cursor.speed > 0.0 {
+                let gap = (recorded.at - last_at).max(0) as f64 / cursor.speed;
+                sleep(Duration::from_secs_f64(gap / 1000.0)).await;
+            }
+
+            cursor.last_at = Some(recorded.at);
+        }
+
+        return Some((Ok(Message::Text(line.into())), cursor));
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for ReplayProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Replay
+    }
+
+    fn stream_url(&self) -> String {
+        format!("file://{}", self.path)
+    }
+
+    async fn open_stream(&self) -> Result<(FrameSink, FrameStream)> {
+        let file = File::open(&self.path).await?;
+        self.start_pass();
+
+        let cursor = ReplayCursor { lines: BufReader::new(file).lines(), last_at: None, speed: self.speed };
+
+        // Subscription frames have nowhere to go, the recording decides what is streamed.
+        let sink = futures_util::sink::drain().sink_map_err(|never| match never {});
+
+        Ok((Box::pin(sink), Box::pin(stream::unfold(cursor, next_frame))))
+    }
+
+    /// Gaps in a recording are replayed faithfully and must not count as a dead socket.
+    fn keepalive_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn subscribe_message(&self, symbol: &str) -> String {
+        symbol.to_string()
+    }
+
+    fn unsubscribe_message(&self, symbol: &str) -> String {
+        symbol.to_string()
+    }
+
+    fn parse_message(&self, text: &str) -> Vec<StreamMessage> {
+        let mut messages = match serde_json::from_str::<RecordedFrame>(text) {
+            Ok(recorded) => match recorded.provider {
+                ProviderKind::Finnhub => finnhub::parse_message(&recorded.frame),
+                ProviderKind::Polygon => polygon::parse_message(&recorded.frame),
+                ProviderKind::Replay => vec![StreamMessage::Unexpected(recorded.frame)],
+            },
+            Err(_) => vec![StreamMessage::Unexpected(text.to_string())],
+        };
+
+        let mut clock = self.clock.lock().expect("Replay clock lock poisoned");
+        for message in &mut messages {
+            if let StreamMessage::Trades(trades) = message {
+                for trade in trades {
+                    trade.timestamp = clock.shift(trade.timestamp);
+                }
+            }
+        }
+
+        messages
+    }
+
+    /// Recordings may mix providers, their condition codes are filtered as they would be live.
+    fn ineligible_conditions(&self) -> Vec<String> {
+        finnhub::ineligible_conditions().into_iter().chain(polygon::ineligible_conditions()).collect()
+    }
+
+    /// Recordings carry no quotes, the pipeline falls back to the first replayed price.
+    async fn get_quote(&self, symbol: &str) -> Result<Quote> {
+        bail!("Quotes are not available while replaying, no quote for {symbol}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use utils::database::finance::Decimal;
+
+    use super::*;
+    use crate::{filter::TickFilter, pipeline::queue_trades, types::{QueuedTrade, RejectReason}};
+
+    const RECORDING: &str = include_str!("../../tests/fixtures/replay.jsonl");
+
+    fn replay_pass(provider: &ReplayProvider, tick_filter: &mut TickFilter, queue: &mut HashMap<String, QueuedTrade>) -> (HashMap<RejectReason, u64>, usize) {
+        let mut rejected: HashMap<RejectReason, u64> = HashMap::new();
+        let mut ready = 0;
+
+        provider.start_pass();
+        for line in RECORDING.lines() {
+            for message in provider.parse_message(line) {
+                match message {
+                    StreamMessage::Trades(trades) => {
+                        for (reason, count) in queue_trades(tick_filter, queue, trades) {
+                            *rejected.entry(reason).or_default() += count;
+                        }
+                    }
+                    StreamMessage::Ready => ready += 1,
+                    StreamMessage::Ignored => {}
+                    other => panic!("Unexpected message {other:?}"),
+                }
+            }
+        }
+
+        (rejected, ready)
+    }
+
+    fn provider() -> ReplayProvider {
+        ReplayProvider { path: String::new(), speed: 0.0, clock: Mutex::default() }
+    }
+
+    #[test]
+    fn replays_recording_into_the_batch_queue() {
+        let provider = provider();
+        let mut tick_filter = TickFilter::new(provider.ineligible_conditions());
+        let mut queue = HashMap::new();
+
+        let (rejected, ready) = replay_pass(&provider, &mut tick_filter, &mut queue);
+
+        assert_eq!(ready, 1);
+        assert_eq!(rejected, HashMap::from([(RejectReason::Condition, 1), (RejectReason::OutOfSequence, 1)]));
+
+        let mut symbols: Vec<&String> = queue.keys().collect();
+        symbols.sort();
+        assert_eq!(symbols, ["AAPL", "MSFT", "NVDA"]);
+
+        let aapl = &queue["AAPL"];
+        assert_eq!(aapl.latest.price, Decimal::new(19000, 2));
+        assert_eq!(aapl.open, Decimal::new(19010, 2));
+        assert_eq!(aapl.high, Decimal::new(19030, 2));
+        assert_eq!(aapl.low, Decimal::new(19000, 2));
+        assert_eq!(aapl.volume, Decimal::from(19));
+
+        assert_eq!(queue["MSFT"].latest.price, Decimal::from(410));
+        assert_eq!(queue["NVDA"].latest.price, Decimal::new(1205, 1));
+        assert_eq!(queue["NVDA"].volume, Decimal::from(100));
+    }
+
+    #[test]
+    fn later_passes_are_not_out_of_sequence() {
+        let provider = provider();
+        let mut tick_filter = TickFilter::new(provider.ineligible_conditions());
+        let mut queue = HashMap::new();
+
+        let (first, _) = replay_pass(&provider, &mut tick_filter, &mut queue);
+        queue.clear();
+        let (second, _) = replay_pass(&provider, &mut tick_filter, &mut queue);
+
+        assert_eq!(second, first);
+        assert_eq!(queue["AAPL"].volume, Decimal::from(19));
+    }
+}
@@ -0,0 +1,83 @@
+use std::env;
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::{AsyncWriteExt, BufWriter}, sync::mpsc};
+use utils::log::{error, info, warn};
+
+use crate::providers::ProviderKind;
+
+pub(crate) const RECORD_PATH_VAR: &str = "FINANCE_RECORD_PATH";
+
+const RECORDER_BUFFER: usize = 4096;
+
+/// One line of a recording, `frame` is the raw websocket text as the provider sent it.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RecordedFrame {
+    /// Unix milliseconds at which the frame was received.
+    pub at: i64,
+    pub provider: ProviderKind,
+    pub frame: String,
+}
+
+/// Appends raw websocket frames to a JSON lines file for later replay.
+#[derive(Clone)]
+pub(crate) struct FrameRecorder {
+    sender: mpsc::Sender<RecordedFrame>,
+}
+
+impl FrameRecorder {
+    /// Starts recording if `FINANCE_RECORD_PATH` is set.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var(RECORD_PATH_VAR).ok()?;
+        let (sender, receiver) = mpsc::channel(RECORDER_BUFFER);
+
+        info!("Recording finance websocket frames to {path}");
+        tokio::spawn(recorder_task(receiver, path));
+
+        Some(Self { sender })
+    }
+
+    pub fn record(&self, provider: ProviderKind, frame: &str) {
+        let recorded = RecordedFrame {
+            at: chrono::Utc::now().timestamp_millis(),
+            provider,
+            frame: frame.to_string(),
+        };
+
+        // Never hold up the read loop for the recording, a full buffer drops the frame.
+        if self.sender.try_send(recorded).is_err() {
+            warn!("Frame recorder is falling behind, dropped a {provider} frame");
+        }
+    }
+}
+
+async fn recorder_task(mut receiver: mpsc::Receiver<RecordedFrame>, path: String) {
+    let mut file = match OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => {
+            error!("Could not open recording file {path}: {e}");
+            return;
+        }
+    };
+
+    while let Some(recorded) = receiver.recv().await {
+        let line = match serde_json::to_string(&recorded) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed serializing recorded frame: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = file.write_all(format!("{line}\n").as_bytes()).await {
+            error!("Failed writing to recording file {path}: {e}");
+        }
+
+        // Flushed once the frames that arrived together are written, not per frame.
+        if receiver.is_empty() && let Err(e) = file.flush().await {
+            error!("Failed writing to recording file {path}: {e}");
+        }
+    }
+
+    let _ = file.flush().await;
+}
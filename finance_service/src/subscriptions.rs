@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+use utils::{database::{finance::{delete_subscription, get_subscriptions, insert_subscription}, portfolios::get_portfolio_symbols, watchlists::get_watchlist_symbols}, log::{info, warn}};
+
+use crate::{metadata::refresh_metadata, refresh_quote, types::{FinanceState, SubscriptionCommand}};
+
+const MAX_SYMBOL_LENGTH: usize = 30;
+
+pub fn normalize_symbol(symbol: &str) -> Result<String> {
+    let symbol = symbol.trim().to_uppercase();
+
+    if symbol.is_empty() || symbol.len() > MAX_SYMBOL_LENGTH {
+        bail!("Symbols must be between 1 and {MAX_SYMBOL_LENGTH} characters");
+    }
+
+    if !symbol.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '.' | '-' | '_' | '^' | '=')) {
+        bail!("Symbol {symbol} contains unsupported characters");
+    }
+
+    Ok(symbol)
+}
+
+/// Fails when making the symbols live would exceed `FINANCE_MAX_LIVE_SYMBOLS`,
+/// symbols that already are live do not count.
+pub async fn ensure_capacity(state: &FinanceState, symbols: &[String]) -> Result<()> {
+    let live = state.subscriptions.read().await;
+    let added = symbols.iter().filter(|symbol| !live.contains(symbol)).count();
+
+    if live.len() + added > state.max_live_symbols {
+        bail!("At most {} symbols can be tracked at once, {} already are", state.max_live_symbols, live.len());
+    }
+
+    Ok(())
+}
+
+/// Persists a new symbol, subscribes to it on the live websocket and starts
+/// seeding its row with the latest quote. Returns `false` if it was already subscribed.
+pub async fn add_subscription(state: &FinanceState, symbol: &str) -> Result<bool> {
+    let symbol = normalize_symbol(symbol)?;
+    let _sync = state.subscription_sync.lock().await;
+
+    ensure_capacity(state, std::slice::from_ref(&symbol)).await?;
+
+    if !insert_subscription(state.pool.clone(), symbol.clone()).await {
+        return Ok(false);
+    }
+
+    track_symbol(state, &symbol).await;
+
+    info!("Added subscription for {symbol}");
+    Ok(true)
+}
+
+/// Removes a symbol from the global list, it stays live while a watchlist
+/// or portfolio still references it. Returns `false` if it was not subscribed.
+pub async fn remove_subscription(state: &FinanceState, symbol: &str) -> Result<bool> {
+    let symbol = normalize_symbol(symbol)?;
+
+    if !delete_subscription(state.pool.clone(), symbol.clone()).await {
+        return Ok(false);
+    }
+
+    sync_subscriptions(state).await;
+
+    info!("Removed subscription for {symbol}");
+    Ok(true)
+}
+
+/// Brings the live symbol set in line with the global subscriptions plus
+/// every symbol referenced by a watchlist or portfolio, run after either changes.
+/// Nothing changes when any of the lists can not be read, an empty list would drop every symbol.
+pub async fn sync_subscriptions(state: &FinanceState) {
+    // Concurrent syncs could each act on a read from before the other's change.
+    let _sync = state.subscription_sync.lock().await;
+
+    let lists = tokio::try_join!(
+        get_subscriptions(state.pool.clone()),
+        get_watchlist_symbols(state.pool.clone()),
+        get_portfolio_symbols(state.pool.clone()),
+    );
+
+    let Ok((subscriptions, watchlist_symbols, portfolio_symbols)) = lists else {
+        warn!("Skipped syncing subscriptions, the referenced symbols could not be read");
+        return;
+    };
+
+    let wanted: HashSet<String> = subscriptions.into_iter()
+        .chain(watchlist_symbols)
+        .chain(portfolio_symbols)
+        .collect();
+
+    let live: HashSet<String> = state.current_subscriptions().await.into_iter().collect();
+
+    for symbol in live.difference(&wanted) {
+        untrack_symbol(state, symbol).await;
+        info!("{symbol} is no longer referenced and was dropped");
+    }
+
+    for symbol in wanted.difference(&live) {
+        if state.subscriptions.read().await.len() >= state.max_live_symbols {
+            warn!("{symbol} stays offline, {} symbols are already live", state.max_live_symbols);
+            continue;
+        }
+
+        track_symbol(state, symbol).await;
+        info!("{symbol} is now live");
+    }
+}
+
+async fn track_symbol(state: &FinanceState, symbol: &str) {
+    {
+        let mut subscriptions = state.subscriptions.write().await;
+        if subscriptions.iter().any(|s| s == symbol) {
+            return;
+        }
+
+        subscriptions.push(symbol.to_string());
+    }
+
+    // Nobody listening means we are between connections, the reconnect subscribes to the full set anyway.
+    let _ = state.subscription_commands.send(SubscriptionCommand::Subscribe(symbol.to_string()));
+
+    state.book.insert_symbol(symbol).await;
+
+    // Seeding waits on the rate limited provider clients, the caller does not.
+    tokio::spawn({
+        let state = state.clone();
+        let symbol = symbol.to_string();
+        async move {
+            refresh_quote(&state, &symbol).await;
+            refresh_metadata(&state, &symbol).await;
+        }
+    });
+}
+
+async fn untrack_symbol(state: &FinanceState, symbol: &str) {
+    state.subscriptions.write().await.retain(|s| s != symbol);
+    let _ = state.subscription_commands.send(SubscriptionCommand::Unsubscribe(symbol.to_string()));
+
+    state.book.remove_symbol(symbol).await;
+}
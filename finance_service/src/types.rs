@@ -1,120 +1,504 @@
-use std::{collections::HashMap, env, fs, pin::Pin, sync::Arc, time::{Duration, Instant}};
+use std::{collections::HashMap, env, fs, str::FromStr, sync::{Arc, atomic::AtomicUsize}};
 
-use reqwest::{Client, header::{HeaderMap, HeaderValue}};
-use serde::{Deserialize, Serialize};
-use tokio::time::Sleep;
-use utils::database::PgPool;
+use chrono::{DateTime, Duration, Utc};
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct TradeUpdate {
-    #[serde(rename = "type")]
-    pub message_type: String,
-    pub data: Vec<TradeData>
-}
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
+use serde_json::value::RawValue;
+use tokio::sync::{Mutex, RwLock, broadcast};
+use utils::{database::{PgPool, finance::Decimal}, log::warn};
+
+use crate::{alerts::AlertEngine, book::PriceBook, calendar::{self, CryptoReference, MarketSession}, movers::MarketMovers, providers::{HttpMetrics, ProviderKind, ProviderRegistry}, recorder::FrameRecorder};
 
 #[derive(Debug, Deserialize, Clone)]
 pub(crate) struct TradeData {
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "p")]
-    pub price: f64,
+    #[serde(rename = "p", deserialize_with = "exact_decimal")]
+    pub price: Decimal,
     #[serde(rename = "t")]
     pub timestamp: u64,
+    #[serde(rename = "v", default, deserialize_with = "exact_decimal")]
+    pub volume: Decimal,
+    /// Exchange trade condition codes, as reported by the provider.
+    #[serde(rename = "c", default, deserialize_with = "deserialize_conditions")]
+    pub conditions: Vec<String>,
+}
+
+/// Reads a provider's number from its JSON text, parsing it as an `f64` first would round it.
+pub(crate) fn exact_decimal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let raw = Box::<RawValue>::deserialize(deserializer)?;
+    let number = raw.get().trim_matches('"');
+
+    Decimal::from_str(number)
+        .or_else(|_| Decimal::from_scientific(number))
+        .map_err(|e| D::Error::custom(format!("invalid decimal {number}: {e}")))
+}
+
+/// Finnhub reports condition codes as strings, Polygon as integers.
+fn deserialize_conditions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Condition {
+        Code(String),
+        Number(i64),
+    }
+
+    let conditions: Option<Vec<Condition>> = Deserialize::deserialize(deserializer)?;
+
+    Ok(conditions.unwrap_or_default().into_iter().map(|condition| match condition {
+        Condition::Code(code) => code,
+        Condition::Number(number) => number.to_string(),
+    }).collect())
+}
+
+/// Every trade of a symbol received since the last batch, only the latest is processed.
+#[derive(Debug, Clone)]
+pub(crate) struct QueuedTrade {
+    pub latest: TradeData,
+    /// Price of the earliest trade.
+    pub open: Decimal,
+    opened_at: u64,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub volume: Decimal,
+    pub trade_count: i32,
+}
+
+impl QueuedTrade {
+    pub fn new(trade: TradeData) -> Self {
+        Self {
+            open: trade.price,
+            opened_at: trade.timestamp,
+            high: trade.price,
+            low: trade.price,
+            volume: trade.volume,
+            trade_count: 1,
+            latest: trade,
+        }
+    }
+
+    /// Providers may deliver trades out of order, so the latest and earliest are picked by timestamp.
+    pub fn add(&mut self, trade: TradeData) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.volume += trade.volume;
+        self.trade_count += 1;
+
+        if trade.timestamp < self.opened_at {
+            self.open = trade.price;
+            self.opened_at = trade.timestamp;
+        }
+
+        if trade.timestamp > self.latest.timestamp {
+            self.latest = trade;
+        }
+    }
+
+    /// Folds in trades of the same symbol that were queued separately.
+    pub fn merge(&mut self, other: QueuedTrade) {
+        self.high = self.high.max(other.high);
+        self.low = self.low.min(other.low);
+        self.volume += other.volume;
+        self.trade_count += other.trade_count;
+
+        if other.opened_at < self.opened_at {
+            self.open = other.open;
+            self.opened_at = other.opened_at;
+        }
+
+        if other.latest.timestamp > self.latest.timestamp {
+            self.latest = other.latest;
+        }
+    }
+}
+
+/// Broad class of a subscribed symbol, crypto pairs are prefixed by their exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetClass {
+    Stock,
+    Crypto,
+}
+
+impl AssetClass {
+    pub fn from_symbol(symbol: &str) -> Self {
+        if symbol.starts_with("BINANCE:") {
+            AssetClass::Crypto
+        } else {
+            AssetClass::Stock
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetClass::Stock => "stock",
+            AssetClass::Crypto => "crypto",
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub(crate) struct BatchStats {
     pub batches_processed: u64,
     pub total_updates_processed: u64,
-    pub errors: u64,
+    pub failed_trades: u64,
+    pub rejected_ticks: HashMap<RejectReason, u64>,
 }
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct QuoteResponse {
-    #[serde(rename = "c")]
-    pub current_price: f64,
-    #[serde(rename = "d")]
-    pub change: f64,
-    #[serde(rename = "dp")]
-    pub percent_change: f64,
-    #[serde(rename = "pc")]
-    pub previous_close: f64
+/// Why a trade was kept away from the displayed price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// Carries a trade condition that does not update the last price, such as an odd lot.
+    Condition,
+    /// Older than a trade that was already accepted.
+    OutOfSequence,
+    /// Too far from the median of the symbol's recent prices.
+    Outlier,
 }
 
-pub(crate) struct WebSocketState {
-    pub update_queue: HashMap<String, TradeData>,
-    pub batch_timer: Option<Pin<Box<Sleep>>>,
-    pub is_processing_batch: bool,
-    pub stats: BatchStats,
-    pub last_log_time: Option<Instant>,
+/// A processed trade as pushed to stream subscribers.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceUpdate {
+    pub symbol: String,
+    pub price: Decimal,
+    pub previous_close: Decimal,
+    pub price_change: Decimal,
+    pub percentage_change: Decimal,
+    pub direction: String,
+    pub volume: Decimal,
+    pub traded_at: chrono::DateTime<chrono::Utc>,
+    /// Decimal places the symbol is quoted with, for display.
+    pub price_scale: i16,
+    pub session: MarketSession,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+    /// Range and volume of the trading day so far.
+    pub day_open: Decimal,
+    pub day_high: Decimal,
+    pub day_low: Decimal,
+    pub day_volume: Decimal,
 }
 
-impl WebSocketState {
-    pub fn new() -> Self {
-        Self {
-            update_queue: HashMap::new(),
-            batch_timer: None,
-            is_processing_batch: false,
-            stats: BatchStats::default(),
-            last_log_time: None,
-        }
+/// Every update accepted in a single `process_batch` run.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceBatch {
+    pub batch: u64,
+    pub updates: Vec<PriceUpdate>,
+}
+
+pub type PriceUpdateSender = broadcast::Sender<Arc<PriceBatch>>;
+
+const PRICE_UPDATE_CHANNEL_CAPACITY: usize = 256;
+
+pub fn price_update_channel() -> PriceUpdateSender {
+    broadcast::channel(PRICE_UPDATE_CHANNEL_CAPACITY).0
+}
+
+/// Changes to the live symbol set, forwarded as frames on the open websocket.
+#[derive(Debug, Clone)]
+pub enum SubscriptionCommand {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+const SUBSCRIPTION_COMMAND_CAPACITY: usize = 64;
+
+const MAX_LIVE_SYMBOLS_VAR: &str = "FINANCE_MAX_LIVE_SYMBOLS";
+const DEFAULT_MAX_LIVE_SYMBOLS: usize = 500;
+
+fn max_live_symbols() -> usize {
+    match env::var(MAX_LIVE_SYMBOLS_VAR).as_deref() {
+        Ok("") | Err(_) => DEFAULT_MAX_LIVE_SYMBOLS,
+        Ok(value) => value.parse().ok().filter(|limit| *limit > 0).unwrap_or_else(|| {
+            warn!("Invalid {MAX_LIVE_SYMBOLS_VAR} {value}, using {DEFAULT_MAX_LIVE_SYMBOLS}");
+            DEFAULT_MAX_LIVE_SYMBOLS
+        }),
     }
 }
 
 #[derive(Clone)]
 pub struct FinanceState {
-    pub api_key: String,
-    pub subscriptions: Vec<String>,
-    pub client: Arc<Client>,
+    pub subscriptions: Arc<RwLock<Vec<String>>>,
+    pub subscription_commands: broadcast::Sender<SubscriptionCommand>,
+    pub providers: Arc<ProviderRegistry>,
+    pub book: Arc<PriceBook>,
+    pub alerts: Arc<AlertEngine>,
+    pub movers: Arc<MarketMovers>,
     pub pool: Arc<PgPool>,
+    /// Most symbols streamed at once across the admin list, watchlists and portfolios.
+    pub max_live_symbols: usize,
+    /// Held while the live symbol set is brought in line with the database.
+    pub(crate) subscription_sync: Arc<Mutex<()>>,
+    /// Where the next news refresh continues in the list of subscribed stocks.
+    pub(crate) news_cursor: Arc<AtomicUsize>,
 }
 
 impl FinanceState {
-    pub fn new(pool: Arc<PgPool>) -> Self {
+    /// Fails on invalid provider configs.
+    pub fn new(pool: Arc<PgPool>) -> anyhow::Result<Self> {
         let file_contents = fs::read_to_string("./configs/subscriptions.json").expect("Finance configs missing...");
-        let subscriptions = serde_json::from_str(&file_contents).expect("Failed parsing finance configs as Json");
+        let subscriptions: Vec<String> = serde_json::from_str(&file_contents).expect("Failed parsing finance configs as Json");
 
-        let api_key = env::var("FINNHUB_API_KEY").expect("Finnhub API key needs to be set in .env");
+        let book = Arc::new(PriceBook::new(Arc::clone(&pool)));
 
-        let mut headers: HeaderMap = HeaderMap::new();
-        headers.append("X-Finnhub-Token", HeaderValue::from_str(&api_key).expect("Failed casting api_key to HeaderValue"));
+        Ok(Self {
+            subscriptions: Arc::new(RwLock::new(subscriptions)),
+            subscription_commands: broadcast::channel(SUBSCRIPTION_COMMAND_CAPACITY).0,
+            providers: Arc::new(ProviderRegistry::from_config()?),
+            alerts: Arc::new(AlertEngine::new(Arc::clone(&pool), Arc::clone(&book))),
+            movers: Arc::new(MarketMovers::new(Arc::clone(&book))),
+            book,
+            pool,
+            max_live_symbols: max_live_symbols(),
+            subscription_sync: Arc::new(Mutex::new(())),
+            news_cursor: Arc::new(AtomicUsize::new(0)),
+        })
+    }
 
-        let client = Client::builder()
-            .default_headers(headers)
-            .timeout(Duration::from_millis(10_000))
-            .build().expect("Failed creating finance Reqwest Client");
+    /// Snapshot of the symbols that should currently be streamed.
+    pub async fn current_subscriptions(&self) -> Vec<String> {
+        self.subscriptions.read().await.clone()
+    }
+}
+
+/// Everything the batching pipeline shares besides its own queue.
+#[derive(Clone)]
+pub(crate) struct PipelineContext {
+    pub providers: Arc<ProviderRegistry>,
+    pub pool: Arc<PgPool>,
+    pub book: Arc<PriceBook>,
+    pub health_state: Arc<Mutex<FinanceHealth>>,
+    pub price_updates: PriceUpdateSender,
+    pub recorder: Option<FrameRecorder>,
+    pub alerts: Arc<AlertEngine>,
+    pub movers: Arc<MarketMovers>,
+    pub crypto_reference: CryptoReference,
+}
+
+const SILENT_FEED_VAR: &str = "FINANCE_HEALTH_SILENT_FEED_SECS";
+const DEAD_FEED_VAR: &str = "FINANCE_HEALTH_DEAD_FEED_SECS";
+const STALE_SYMBOL_VAR: &str = "FINANCE_HEALTH_STALE_SYMBOL_SECS";
+
+/// Ages after which `/finance/health` stops reporting healthy.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthThresholds {
+    /// A provider without frames for this long is degraded.
+    pub silent_feed: Duration,
+    /// A provider without frames for this long is unhealthy.
+    pub dead_feed: Duration,
+    /// A subscribed symbol without trades for this long is degraded.
+    pub stale_symbol: Duration,
+}
+
+impl HealthThresholds {
+    pub fn from_env() -> Self {
+        let seconds = |var: &str, default: i64| {
+            let seconds = env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+            Duration::seconds(seconds)
+        };
 
         Self {
-            api_key,
-            subscriptions,
-            client: Arc::new(client),
-            pool,
+            silent_feed: seconds(SILENT_FEED_VAR, 60),
+            dead_feed: seconds(DEAD_FEED_VAR, 300),
+            stale_symbol: seconds(STALE_SYMBOL_VAR, 900),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// Live state of one provider's websockets.
+#[derive(Serialize, Clone, Default)]
+pub struct ConnectionHealth {
+    /// Whether every shard is connected.
+    pub connected: bool,
+    pub connected_since: Option<DateTime<Utc>>,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub reconnects: u64,
+    /// Sockets the provider's symbols are split over.
+    pub shards: usize,
+    pub connected_shards: usize,
+    #[serde(skip)]
+    shard_states: HashMap<usize, bool>,
+}
+
+impl ConnectionHealth {
+    fn update_shards(&mut self) {
+        self.shards = self.shard_states.len();
+        self.connected_shards = self.shard_states.values().filter(|connected| **connected).count();
+
+        let connected = self.shards > 0 && self.connected_shards == self.shards;
+        if connected != self.connected {
+            self.connected = connected;
+            self.connected_since = connected.then(Utc::now);
         }
     }
 }
 
 #[derive(Serialize)]
-pub struct FinanceHealth {
-    pub status: String,
+pub struct SymbolHealth {
+    pub symbol: String,
+    pub last_trade_at: Option<DateTime<Utc>>,
+    /// Seconds since the last trade, or since startup if none arrived yet.
+    pub age_seconds: i64,
+    /// No trade for the stale threshold while the symbol's market is open, counted from the session start at the earliest.
+    pub stale: bool,
+}
+
+#[derive(Serialize)]
+pub struct FinanceHealthReport {
+    pub status: HealthStatus,
+    /// Human readable reasons for a status other than healthy.
+    pub issues: Vec<String>,
     pub batch_number: u64,
+    pub batch_errors: u64,
+    pub failed_trades: u64,
+    /// Trades dropped by the tick filter since startup.
+    pub rejected_ticks: HashMap<RejectReason, u64>,
+    pub connections: HashMap<ProviderKind, ConnectionHealth>,
+    /// Quote API usage per provider, including calls held back by the rate limit.
+    pub quote_api: HashMap<ProviderKind, HttpMetrics>,
+    pub symbols: Vec<SymbolHealth>,
+}
+
+pub struct FinanceHealth {
+    started_at: DateTime<Utc>,
+    thresholds: HealthThresholds,
+    batch_number: u64,
+    batch_errors: u64,
+    failed_trades: u64,
+    rejected_ticks: HashMap<RejectReason, u64>,
+    connections: HashMap<ProviderKind, ConnectionHealth>,
+    last_trades: HashMap<String, DateTime<Utc>>,
 }
 
 impl FinanceHealth {
     pub fn new() -> Self {
         Self {
-            status: String::from("healthy"),
+            started_at: Utc::now(),
+            thresholds: HealthThresholds::from_env(),
             batch_number: 0,
+            batch_errors: 0,
+            failed_trades: 0,
+            rejected_ticks: HashMap::new(),
+            connections: HashMap::new(),
+            last_trades: HashMap::new(),
         }
     }
 
-    pub(crate) fn set_batch(&mut self, number: u64) {
-        self.batch_number = number
+    /// Batch numbers are counted per provider, the error counts accumulate for the process lifetime.
+    pub(crate) fn record_batch(&mut self, number: u64, failed_trades: u64, updates: &[PriceUpdate]) {
+        // Rollovers finish after the batch they were taken from, the number must not go back.
+        self.batch_number = self.batch_number.max(number);
+        self.failed_trades += failed_trades;
+
+        for update in updates {
+            let last_trade = self.last_trades.entry(update.symbol.clone()).or_insert(update.traded_at);
+            *last_trade = (*last_trade).max(update.traded_at);
+        }
     }
 
-    pub fn get_health(&self) -> Self {
-        Self {
-            status: self.status.clone(),
+    /// A failed write of the price book, it is retried until it lands and prices are served all the same.
+    pub(crate) fn record_persist_failure(&mut self) {
+        self.batch_errors += 1;
+    }
+
+    pub(crate) fn record_rejected(&mut self, reasons: &HashMap<RejectReason, u64>) {
+        for (reason, count) in reasons {
+            *self.rejected_ticks.entry(*reason).or_default() += count;
+        }
+    }
+
+    pub(crate) fn set_connected(&mut self, kind: ProviderKind, shard: usize, connected: bool) {
+        let connection = self.connections.entry(kind).or_default();
+
+        connection.shard_states.insert(shard, connected);
+        connection.update_shards();
+    }
+
+    /// Forgets a shard that was closed because its symbols fit on the remaining ones.
+    pub(crate) fn remove_shard(&mut self, kind: ProviderKind, shard: usize) {
+        let connection = self.connections.entry(kind).or_default();
+
+        connection.shard_states.remove(&shard);
+        connection.update_shards();
+    }
+
+    pub(crate) fn record_reconnect(&mut self, kind: ProviderKind) {
+        self.connections.entry(kind).or_default().reconnects += 1;
+    }
+
+    pub(crate) fn record_message(&mut self, kind: ProviderKind) {
+        self.connections.entry(kind).or_default().last_message_at = Some(Utc::now());
+    }
+
+    /// Evaluates the thresholds against the current state for the given subscriptions.
+    pub fn get_health(&self, subscriptions: &[String], providers: &ProviderRegistry) -> FinanceHealthReport {
+        let now = Utc::now();
+        let mut status = HealthStatus::Healthy;
+        let mut issues = Vec::new();
+
+        for (kind, connection) in &self.connections {
+            let silence = now - connection.last_message_at.unwrap_or(self.started_at);
+
+            let connection_status = if silence >= self.thresholds.dead_feed {
+                HealthStatus::Unhealthy
+            } else if !connection.connected || silence >= self.thresholds.silent_feed {
+                HealthStatus::Degraded
+            } else {
+                HealthStatus::Healthy
+            };
+
+            if connection_status != HealthStatus::Healthy {
+                let state = if connection.connected { "connected".to_string() } else { format!("disconnected ({} of {} shards up)", connection.connected_shards, connection.shards) };
+                issues.push(format!("{kind} feed is {state} and silent for {}s", silence.num_seconds()));
+                status = status.max(connection_status);
+            }
+        }
+
+        let symbols: Vec<SymbolHealth> = subscriptions.iter().map(|symbol| {
+            let last_trade_at = self.last_trades.get(symbol).copied();
+            let last_seen = last_trade_at.unwrap_or(self.started_at);
+            let asset_class = AssetClass::from_symbol(symbol);
+
+            // Closed markets have no trades to miss, and the night before a session does not count against it.
+            let quiet_since = match calendar::session(asset_class, now) {
+                MarketSession::Closed => None,
+                _ if asset_class == AssetClass::Crypto => Some(last_seen),
+                _ => Some(last_seen.max(calendar::trading_day_start(asset_class, calendar::trading_day(asset_class, now)))),
+            };
+
+            SymbolHealth {
+                symbol: symbol.clone(),
+                last_trade_at,
+                age_seconds: (now - last_seen).num_seconds(),
+                stale: quiet_since.is_some_and(|since| now - since >= self.thresholds.stale_symbol),
+            }
+        }).collect();
+
+        let stale_count = symbols.iter().filter(|s| s.stale).count();
+        if stale_count > 0 {
+            issues.push(format!("{stale_count} of {} symbols have no trade in the last {}s of their session", symbols.len(), self.thresholds.stale_symbol.num_seconds()));
+            status = status.max(HealthStatus::Degraded);
+        }
+
+        FinanceHealthReport {
+            status,
+            issues,
             batch_number: self.batch_number,
+            batch_errors: self.batch_errors,
+            failed_trades: self.failed_trades,
+            rejected_ticks: self.rejected_ticks.clone(),
+            connections: self.connections.clone(),
+            quote_api: providers.http_metrics(),
+            symbols,
         }
     }
-}
\ No newline at end of file
+}
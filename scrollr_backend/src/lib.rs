@@ -1,15 +1,26 @@
-use std::{collections::HashMap, env, sync::Arc, time::{Duration, Instant}};
+use std::{collections::{HashMap, HashSet}, env, sync::Arc, time::{Duration, Instant}};
 
 use axum::{Json, http::{HeaderMap, HeaderValue, StatusCode, header::AUTHORIZATION}, response::{IntoResponse, Response}};
 use axum_extra::extract::{CookieJar, cookie::{Cookie, SameSite}};
-use finance_service::types::FinanceHealth;
+use finance_service::{calendar::{self, MarketSession}, normalize_symbol, types::{AssetClass, FinanceHealth, FinanceState, PriceBatch, PriceUpdate, PriceUpdateSender, price_update_channel}};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
 use secrecy::SecretString;
 pub use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use utils::{database::{PgPool, initialize_pool}, log::warn};
+use utils::{database::{PgPool, finance::{DatabaseTradeData, Decimal, NaiveDate, SymbolMetadata, Utc}, initialize_pool, portfolios::PortfolioLot}, log::warn};
 use yahoo_fantasy::{api::Client, types::Tokens};
 
+use crate::scheduler::{ScheduledJob, load_schedules};
+
+pub mod scheduler;
+
+pub const MAX_WATCHLISTS_PER_USER: usize = 20;
+pub const MAX_WATCHLIST_SYMBOLS: usize = 50;
+pub const MAX_ALERTS_PER_USER: usize = 50;
+pub const MAX_PORTFOLIO_HOLDINGS: usize = 100;
+pub const MAX_HOLDING_LOTS: usize = 50;
+
 #[derive(Serialize)]
 pub struct ErrorCodeResponse {
     status: String,
@@ -27,6 +38,152 @@ impl ErrorCodeResponse {
     }
 }
 
+/// Public representation of a symbol's latest price, kept separate from the
+/// `trades` table layout so the schema can change without breaking clients.
+#[derive(Serialize)]
+pub struct FinanceQuote {
+    pub symbol: String,
+    pub asset_class: AssetClass,
+    pub price: Decimal,
+    pub previous_close: Decimal,
+    pub price_change: Decimal,
+    pub percentage_change: Decimal,
+    /// Decimal places the symbol is quoted with, for display.
+    pub price_scale: i16,
+    pub direction: String,
+    pub last_updated: chrono::DateTime<Utc>,
+    /// Trading day `previous_close` is the reference for.
+    pub session_date: Option<NaiveDate>,
+    pub session: MarketSession,
+    pub market_open: bool,
+    /// Range and volume of `session_date` so far, pre-market included. `None` until the first trade of the day.
+    pub day_open: Option<Decimal>,
+    pub day_high: Option<Decimal>,
+    pub day_low: Option<Decimal>,
+    pub day_volume: Decimal,
+    /// `None` until the symbol's metadata has been fetched.
+    pub profile: Option<FinanceProfile>,
+}
+
+/// Display data of a symbol, such as "Apple Inc" and its logo for `AAPL`.
+#[derive(Serialize)]
+pub struct FinanceProfile {
+    pub name: Option<String>,
+    pub logo_url: Option<String>,
+    pub exchange: Option<String>,
+    pub currency: Option<String>,
+    pub sector: Option<String>,
+}
+
+impl From<SymbolMetadata> for FinanceProfile {
+    fn from(metadata: SymbolMetadata) -> Self {
+        Self {
+            name: metadata.name,
+            logo_url: metadata.logo_url,
+            exchange: metadata.exchange,
+            currency: metadata.currency,
+            sector: metadata.sector,
+        }
+    }
+}
+
+impl FinanceQuote {
+    pub fn new(trade: DatabaseTradeData, metadata: Option<SymbolMetadata>) -> Self {
+        let asset_class = AssetClass::from_symbol(&trade.symbol);
+        let session = calendar::session(asset_class, Utc::now());
+
+        Self {
+            asset_class,
+            symbol: trade.symbol,
+            price: trade.price,
+            previous_close: trade.previous_close,
+            price_change: trade.price_change,
+            percentage_change: trade.percentage_change,
+            price_scale: trade.price_scale,
+            direction: trade.direction,
+            last_updated: trade.last_updated,
+            session_date: trade.session_date,
+            session,
+            market_open: session.is_open(),
+            day_open: trade.day_open,
+            day_high: trade.day_high,
+            day_low: trade.day_low,
+            day_volume: trade.day_volume,
+            profile: metadata.map(FinanceProfile::from),
+        }
+    }
+}
+
+/// Splits a comma separated query value such as `AAPL,binance:btcusdt` into normalized symbols.
+pub fn parse_symbol_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|s| s.trim().to_uppercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Normalizes and de-duplicates the symbols of a watchlist, keeping their order.
+pub fn watchlist_symbols(symbols: &[String]) -> anyhow::Result<Vec<String>> {
+    if symbols.len() > MAX_WATCHLIST_SYMBOLS {
+        anyhow::bail!("Watchlists hold at most {MAX_WATCHLIST_SYMBOLS} symbols");
+    }
+
+    let mut normalized: Vec<String> = Vec::with_capacity(symbols.len());
+    for symbol in symbols {
+        let symbol = normalize_symbol(symbol)?;
+        if !normalized.contains(&symbol) {
+            normalized.push(symbol);
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Checks the lots of a holding, quantities must be positive and costs can not be negative.
+pub fn holding_lots(lots: Vec<PortfolioLot>) -> anyhow::Result<Vec<PortfolioLot>> {
+    if lots.is_empty() || lots.len() > MAX_HOLDING_LOTS {
+        anyhow::bail!("Holdings are made up of between 1 and {MAX_HOLDING_LOTS} lots");
+    }
+
+    for lot in &lots {
+        if lot.quantity <= Decimal::ZERO {
+            anyhow::bail!("Lot quantities must be positive");
+        }
+
+        if lot.cost_basis < Decimal::ZERO {
+            anyhow::bail!("Lot cost bases can not be negative");
+        }
+    }
+
+    Ok(lots)
+}
+
+/// Per-connection selection of symbols for the finance streams, `None` forwards everything.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolFilter {
+    symbols: Option<HashSet<String>>,
+}
+
+impl SymbolFilter {
+    pub fn new(symbols: Option<Vec<String>>) -> Self {
+        Self {
+            symbols: symbols.map(|list| list.into_iter().map(|s| s.trim().to_uppercase()).collect()),
+        }
+    }
+
+    pub fn from_query(symbols: Option<&str>) -> Self {
+        Self::new(symbols.map(parse_symbol_list))
+    }
+
+    pub fn matches(&self, symbol: &str) -> bool {
+        self.symbols.as_ref().is_none_or(|symbols| symbols.contains(symbol))
+    }
+
+    pub fn apply<'a>(&self, batch: &'a PriceBatch) -> Vec<&'a PriceUpdate> {
+        batch.updates.iter().filter(|update| self.matches(&update.symbol)).collect()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SchedulePayload {
     pub schedule_type: String,
@@ -42,13 +199,23 @@ pub struct ServerState {
     pub csrf_tokens: Arc<Mutex<HashMap<String, Instant>>>,
     pub client: Client,
 
+    pub admin_api_key: Option<SecretString>,
+    pub user_auth: Option<UserAuth>,
+
+    pub finance_state: FinanceState,
     pub finance_health: Arc<Mutex<FinanceHealth>>,
+    pub finance_updates: PriceUpdateSender,
+
+    pub schedules: Arc<Vec<ScheduledJob>>,
 }
 
 impl ServerState {
     pub async fn new() -> Self {
+        let db_pool = Arc::new(initialize_pool().await.expect("Failed to initialize database pool"));
+
         Self {
-            db_pool: Arc::new(initialize_pool().await.expect("Failed to initialize database pool")),
+            finance_state: FinanceState::new(Arc::clone(&db_pool)).expect("Failed to initialize the finance service"),
+            db_pool,
             client_id: env::var("YAHOO_CLIENT_ID").expect("Yahoo client ID must be set in .env"),
             client_secret: SecretString::new(
                 env::var("YAHOO_CLIENT_SECRET")
@@ -58,8 +225,16 @@ impl ServerState {
             yahoo_callback: format!("https://{}{}", env::var("DOMAIN_NAME").unwrap(), env::var("YAHOO_CALLBACK_URL").expect("Yahoo callback URL must be set in .env")),
             csrf_tokens: Arc::new(Mutex::new(HashMap::new())),
             client: Client::new(),
+            admin_api_key: env::var("ADMIN_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())
+                .map(|key| SecretString::new(key.into_boxed_str())),
+            user_auth: UserAuth::from_env(),
 
             finance_health: Arc::new(Mutex::new(FinanceHealth::new())),
+            finance_updates: price_update_channel(),
+
+            schedules: Arc::new(load_schedules()),
         }
     }
 
@@ -71,6 +246,80 @@ impl ServerState {
     }
 }
 
+/// Checks the `Authorization: Bearer <ADMIN_API_KEY>` header and returns the error
+/// response to send if it does not match. Admin routes are unavailable entirely
+/// when no key is configured.
+pub fn admin_rejection(headers: &HeaderMap, web_state: &ServerState) -> Option<Response> {
+    let Some(admin_key) = &web_state.admin_api_key else {
+        return Some(ErrorCodeResponse::new(StatusCode::FORBIDDEN, "Admin API is disabled"));
+    };
+
+    let provided = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value));
+
+    match provided {
+        Some(key) if constant_time_eq(key.as_bytes(), admin_key.expose_secret().as_bytes()) => None,
+        _ => Some(ErrorCodeResponse::new(StatusCode::UNAUTHORIZED, "Unauthorized, invalid admin key")),
+    }
+}
+
+/// Verifies the HS256 access tokens of the auth provider our users sign in
+/// with, e.g. the JWT secret of the Supabase project.
+#[derive(Clone)]
+pub struct UserAuth {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+#[derive(Deserialize)]
+struct UserClaims {
+    sub: String,
+}
+
+impl UserAuth {
+    /// `None` when `AUTH_JWT_SECRET` is not set, which disables every per-user route.
+    pub fn from_env() -> Option<Self> {
+        let secret = env::var("AUTH_JWT_SECRET").ok().filter(|secret| !secret.is_empty())?;
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        match env::var("AUTH_JWT_AUDIENCE") {
+            Ok(audience) if !audience.is_empty() => validation.set_audience(&[audience]),
+            _ => validation.validate_aud = false,
+        }
+
+        Some(Self { key: DecodingKey::from_secret(secret.as_bytes()), validation })
+    }
+}
+
+/// Id (`sub` claim) of the user whose `Authorization: Bearer <token>` header
+/// was sent, or the status and message to respond with instead.
+pub fn authenticated_user(headers: &HeaderMap, web_state: &ServerState) -> Result<String, (StatusCode, String)> {
+    let Some(auth) = &web_state.user_auth else {
+        return Err((StatusCode::FORBIDDEN, String::from("User accounts are disabled")));
+    };
+
+    let token = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let Some(token) = token else {
+        return Err((StatusCode::UNAUTHORIZED, String::from("Unauthorized, missing bearer token")));
+    };
+
+    decode::<UserClaims>(token, &auth.key, &auth.validation)
+        .map(|data| data.claims.sub)
+        .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Unauthorized, {e}")))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct RefreshBody {
     refresh_token: String
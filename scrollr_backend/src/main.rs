@@ -1,20 +1,22 @@
-use std::{env, fs::{self}, net::{IpAddr, Ipv4Addr, SocketAddr}, path::PathBuf, sync::Arc, time::{Duration, Instant}};
+use std::{collections::HashMap, convert::Infallible, env, net::{IpAddr, Ipv4Addr, SocketAddr}, path::PathBuf, sync::Arc, time::{Duration, Instant}};
 
-use axum::{Json, Router, extract::{Path, Query, State}, http::{HeaderMap, HeaderValue, StatusCode, header::{self, REFERRER_POLICY}}, response::{Html, IntoResponse, Redirect, Response}, routing::{get, post}};
+use axum::{Json, Router, extract::{Path, Query, State, ws::{Message, WebSocket, WebSocketUpgrade}}, http::{HeaderMap, HeaderValue, StatusCode, header::{self, REFERRER_POLICY}}, response::{Html, IntoResponse, Redirect, Response, sse::{Event, KeepAlive, Sse}}, routing::{delete, get, post, put}};
 use axum_extra::extract::{CookieJar, cookie::{Cookie, SameSite}};
 use axum_server::tls_rustls::RustlsConfig;
-use finance_service::{start_finance_services, types::FinanceState, update_all_previous_closes};
-use futures_util::{StreamExt, future::join_all};
+use chrono::{DateTime, Utc};
+use finance_service::{add_subscription, alerts::{AlertCondition, create_alert, delete_alert, list_alerts}, book::PriceBook, ensure_capacity, normalize_symbol, portfolio::{batch_touches, value_portfolio}, remove_subscription, start_finance_services, sync_subscriptions, types::{AssetClass, HealthStatus, PriceBatch}, update_all_previous_closes};
+use futures_util::{StreamExt, future::join_all, stream};
 use dotenv::dotenv;
 use rcgen::generate_simple_self_signed;
-use scrollr_backend::{ErrorCodeResponse, RefreshBody, SchedulePayload, ServerState, get_access_token, update_tokens};
+use scrollr_backend::{ErrorCodeResponse, FinanceQuote, MAX_ALERTS_PER_USER, MAX_PORTFOLIO_HOLDINGS, MAX_WATCHLISTS_PER_USER, RefreshBody, SchedulePayload, ServerState, SymbolFilter, admin_rejection, authenticated_user, scheduler::{load_league_configs, run_job, start_scheduler}, get_access_token, holding_lots, parse_symbol_list, update_tokens, watchlist_symbols};
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sports_service::{frequent_poll, start_sports_service};
+use tokio::sync::broadcast::{Receiver, error::RecvError};
 use tokio_rustls_acme::{AcmeConfig, caches::DirCache, tokio_rustls::rustls::ServerConfig};
 use tower_http::{cors::{self, AllowOrigin, CorsLayer}, set_header::SetRequestHeaderLayer};
-use utils::{database::sports::LeagueConfigs, log::{error, info, init_async_logger, warn}};
+use utils::{database::{alerts::get_alert_events, finance::{CandleInterval, DatabaseTradeData, Decimal, SymbolMetadata, get_candles}, news::get_news, portfolios::{Holding, PortfolioLot, delete_holding, get_holdings, replace_holding}, scheduler::{JobRun, get_last_job_runs}, watchlists::{Uuid, delete_watchlist, get_watchlist, get_watchlists, insert_watchlist, update_watchlist}}, log::{error, info, init_async_logger, warn}};
 use yahoo_fantasy::{api::{debug_league_stats, get_league_standings, get_matchups, get_team_roster, get_user_leagues}, exchange_for_token, stats::{BasketballStats, FootballStats, HockeyStats, StatDecode}, types::{LeagueStandings, Roster, Tokens}, yahoo};
 
 #[tokio::main]
@@ -35,12 +37,31 @@ async fn main() {
 
     let web_state = ServerState::new().await;
 
-    handles.push(tokio::spawn(start_finance_services(web_state.db_pool.clone(), Arc::clone(&web_state.finance_health))));
+    handles.push(tokio::spawn(start_finance_services(web_state.finance_state.clone(), Arc::clone(&web_state.finance_health), web_state.finance_updates.clone())));
     handles.push(tokio::spawn(start_sports_service(web_state.db_pool.clone())));
+    handles.push(tokio::spawn(start_scheduler(web_state.clone())));
 
     let app = Router::new()
         .route("/", post(handler))
         .route("/finance/health", get(finance_health))
+        .route("/finance/candles/{symbol}", get(finance_candles))
+        .route("/finance/quotes", get(finance_quotes))
+        .route("/finance/quotes/{symbol}", get(finance_quote))
+        .route("/finance/movers", get(finance_movers))
+        .route("/finance/news", get(finance_news))
+        .route("/finance/stream", get(finance_stream))
+        .route("/finance/stream/ws", get(finance_stream_ws))
+        .route("/finance/admin/subscriptions", get(list_finance_subscriptions).post(create_finance_subscription))
+        .route("/finance/admin/subscriptions/{symbol}", delete(delete_finance_subscription))
+        .route("/finance/watchlists", get(list_watchlists).post(create_watchlist))
+        .route("/finance/watchlists/{id}", get(get_user_watchlist).put(update_user_watchlist).delete(delete_user_watchlist))
+        .route("/finance/portfolio", get(user_portfolio))
+        .route("/finance/portfolio/{symbol}", put(update_user_holding).delete(delete_user_holding))
+        .route("/finance/alerts", get(list_user_alerts).post(create_user_alert))
+        .route("/finance/alerts/history", get(user_alert_history))
+        .route("/finance/alerts/{id}", delete(delete_user_alert))
+        .route("/admin/jobs", get(list_scheduled_jobs))
+        .route("/admin/jobs/{name}/run", post(run_scheduled_job))
         .route("/yahoo/start", get(get_yahoo_handler))
         .route("/yahoo/callback", get(yahoo_callback))
         .route("/yahoo/leagues", get(user_leagues).post(user_leagues))
@@ -135,39 +156,22 @@ async fn handler(State(web_state): State<ServerState>, Json(payload): Json<Sched
     let pool = web_state.db_pool;
     match payload.schedule_type.as_str() {
         "finance" => {
-            let state = FinanceState::new(pool);
-
             info!("Running daily finance job...");
-            update_all_previous_closes(state).await;
+            update_all_previous_closes(web_state.finance_state).await;
             info!("Previous closes updated!");
         }
 
         "sports" => {
             info!("Starting frequent polling for the following leagues {:?}", payload.data);
-            let mut leagues = Vec::new();
 
-            let file_contents = match fs::read_to_string("./configs/leagues.json") {
-                Ok(contents) => contents,
-                Err(e) => {
-                    error!("Failed to read leagues config file: {e}");
-                    return;
-                }
-            };
-
-            let leagues_to_ingest: Vec<LeagueConfigs> = match serde_json::from_str(&file_contents) {
+            let leagues = match load_league_configs(&payload.data) {
                 Ok(leagues) => leagues,
                 Err(e) => {
-                    error!("Failed to parse leagues config JSON: {e}");
+                    error!("{e:#}");
                     return;
                 }
             };
 
-            for league in leagues_to_ingest {
-                if payload.data.contains(&league.name) {
-                    leagues.push(league);
-                }
-            }
-
             frequent_poll(leagues, &pool).await;
         }
         _ => warn!("Unexpected POST payload {}", payload.schedule_type),
@@ -498,9 +502,695 @@ async fn get_debug_league_stats(jar: CookieJar, State(web_state): State<ServerSt
 }
 
 async fn finance_health(State(web_state): State<ServerState>) -> impl IntoResponse {
-    let health = web_state.finance_health.lock().await.get_health();
+    let subscriptions = web_state.finance_state.current_subscriptions().await;
+    let health = web_state.finance_health.lock().await.get_health(&subscriptions, &web_state.finance_state.providers);
+
+    // Load balancers and uptime checks only look at the status code.
+    let status = match health.status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+
+    (status, Json(health))
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum QuoteSort {
+    Symbol,
+    PercentChange,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+struct QuotesQuery {
+    symbols: Option<String>,        // Comma separated, e.g. "AAPL,BINANCE:BTCUSDT"
+    watchlist: Option<Uuid>,        // Requires authentication, takes precedence over symbols
+    asset_class: Option<AssetClass>,
+    sort: Option<QuoteSort>,
+    order: Option<SortOrder>,
+}
+
+/// Symbols of a watchlist owned by the authenticated user, other users' watchlists are not found.
+async fn owned_watchlist_symbols(id: Uuid, headers: &HeaderMap, web_state: &ServerState) -> Result<Vec<String>, (StatusCode, String)> {
+    let user_id = authenticated_user(headers, web_state)?;
+
+    match get_watchlist(web_state.db_pool.clone(), id).await {
+        Some(watchlist) if watchlist.user_id == user_id => Ok(watchlist.symbols),
+        _ => Err((StatusCode::NOT_FOUND, format!("No watchlist found for {id}"))),
+    }
+}
+
+async fn finance_quotes(Query(query): Query<QuotesQuery>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let requested_symbols: Option<Vec<String>> = match query.watchlist {
+        Some(id) => match owned_watchlist_symbols(id, &headers, &web_state).await {
+            Ok(symbols) => Some(symbols),
+            Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+        },
+        None => query.symbols.as_deref().map(parse_symbol_list),
+    };
+
+    let trades: Vec<DatabaseTradeData> = web_state.finance_state.book.get_all().await
+        .into_iter()
+        .filter(|trade| requested_symbols.as_ref().is_none_or(|symbols| symbols.contains(&trade.symbol)))
+        .collect();
+
+    let symbols: Vec<String> = trades.iter().map(|trade| trade.symbol.clone()).collect();
+    let mut metadata: HashMap<String, SymbolMetadata> = web_state.finance_state.book.get_metadata(&symbols).await;
+
+    let mut quotes: Vec<FinanceQuote> = trades.into_iter()
+        .map(|trade| {
+            let metadata = metadata.remove(&trade.symbol);
+            FinanceQuote::new(trade, metadata)
+        })
+        .filter(|quote| query.asset_class.is_none_or(|class| class == quote.asset_class))
+        .collect();
+
+    let sort = query.sort.unwrap_or(QuoteSort::Symbol);
+    let order = query.order.unwrap_or(if sort == QuoteSort::PercentChange { SortOrder::Desc } else { SortOrder::Asc });
+
+    match sort {
+        QuoteSort::Symbol => quotes.sort_by(|a, b| a.symbol.cmp(&b.symbol)),
+        QuoteSort::PercentChange => quotes.sort_by_key(|quote| quote.percentage_change),
+    }
+
+    if let SortOrder::Desc = order {
+        quotes.reverse();
+    }
+
+    Json(json!({
+        "quotes": quotes,
+    })).into_response()
+}
+
+async fn finance_quote(Path(symbol): Path<String>, State(web_state): State<ServerState>) -> Response {
+    let symbol = symbol.to_uppercase();
+
+    match web_state.finance_state.book.get(&symbol).await {
+        Some(trade) => {
+            let metadata = web_state.finance_state.book.get_metadata(&[symbol]).await.into_values().next();
+            Json(FinanceQuote::new(trade, metadata)).into_response()
+        }
+        None => ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No quote found for {symbol}")),
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    symbols: Option<String>,        // Comma separated, omitted to receive every symbol
+    watchlist: Option<Uuid>,        // Requires authentication, takes precedence over symbols
+    portfolio: Option<bool>,        // Requires authentication, defaults the symbols to the holdings
+}
+
+/// A portfolio followed by a stream, its holdings are read once when the stream opens.
+struct StreamPortfolio {
+    book: Arc<PriceBook>,
+    holdings: Vec<Holding>,
+}
+
+impl StreamPortfolio {
+    async fn json(&self) -> serde_json::Value {
+        json!(value_portfolio(&self.book, self.holdings.clone()).await)
+    }
+
+    /// The portfolio valued again, if the batch moved any of its holdings.
+    async fn json_after(&self, batch: &PriceBatch) -> Option<serde_json::Value> {
+        if batch_touches(&self.holdings, batch) {
+            Some(self.json().await)
+        } else {
+            None
+        }
+    }
+}
+
+/// Filter and portfolio of a price stream that is about to open.
+async fn open_stream(query: &StreamQuery, headers: &HeaderMap, web_state: &ServerState) -> Result<(SymbolFilter, Option<StreamPortfolio>), (StatusCode, String)> {
+    let portfolio = if query.portfolio.unwrap_or(false) {
+        let user_id = authenticated_user(headers, web_state)?;
+
+        Some(StreamPortfolio {
+            book: Arc::clone(&web_state.finance_state.book),
+            holdings: get_holdings(web_state.db_pool.clone(), user_id).await,
+        })
+    } else {
+        None
+    };
+
+    let filter = match (&portfolio, &query.symbols, query.watchlist) {
+        (Some(portfolio), None, None) => SymbolFilter::new(Some(portfolio.holdings.iter().map(|holding| holding.symbol.clone()).collect())),
+        _ => stream_filter(query, headers, web_state).await?,
+    };
+
+    Ok((filter, portfolio))
+}
+
+/// Initial filter of a price stream, a watchlist's symbols are read once when the stream opens.
+/// Only a watchlist that is unknown or not the user's fails.
+async fn stream_filter(query: &StreamQuery, headers: &HeaderMap, web_state: &ServerState) -> Result<SymbolFilter, (StatusCode, String)> {
+    match query.watchlist {
+        Some(id) => Ok(SymbolFilter::new(Some(owned_watchlist_symbols(id, headers, web_state).await?))),
+        None => Ok(SymbolFilter::from_query(query.symbols.as_deref())),
+    }
+}
+
+fn price_batch_json(batch: &PriceBatch, filter: &SymbolFilter) -> Option<serde_json::Value> {
+    let updates = filter.apply(batch);
+
+    if updates.is_empty() {
+        return None;
+    }
+
+    Some(json!({
+        "batch": batch.batch,
+        "updates": updates,
+    }))
+}
+
+fn sse_event(name: &str, payload: serde_json::Value) -> Option<Result<Event, Infallible>> {
+    Event::default().event(name).json_data(payload)
+        .inspect_err(|e| error!("Failed to serialize {name} event for SSE: {e}"))
+        .ok()
+        .map(Ok)
+}
+
+async fn finance_stream(Query(query): Query<StreamQuery>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let (filter, portfolio) = match open_stream(&query, &headers, &web_state).await {
+        Ok(stream) => stream,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+    let receiver = web_state.finance_updates.subscribe();
+
+    let opening = match &portfolio {
+        Some(portfolio) => sse_event("portfolio", portfolio.json().await).into_iter().collect(),
+        None => Vec::new(),
+    };
+
+    let updates = stream::unfold((receiver, filter, portfolio), |(mut receiver, filter, portfolio)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(batch) => {
+                    let mut events: Vec<Result<Event, Infallible>> = Vec::new();
+
+                    if let Some(payload) = price_batch_json(&batch, &filter) {
+                        events.extend(sse_event("prices", payload));
+                    }
+
+                    if let Some(portfolio) = &portfolio && let Some(payload) = portfolio.json_after(&batch).await {
+                        events.extend(sse_event("portfolio", payload));
+                    }
+
+                    if !events.is_empty() {
+                        return Some((events, (receiver, filter, portfolio)));
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => warn!("SSE client lagging behind, skipped {skipped} price batches"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(opening).chain(updates.flat_map(stream::iter));
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+#[derive(Deserialize)]
+struct StreamFilterMessage {
+    symbols: Option<Vec<String>>,
+}
+
+async fn finance_stream_ws(ws: WebSocketUpgrade, Query(query): Query<StreamQuery>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let (filter, portfolio) = match open_stream(&query, &headers, &web_state).await {
+        Ok(stream) => stream,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+    let receiver = web_state.finance_updates.subscribe();
+
+    ws.on_upgrade(move |socket| stream_price_updates(socket, receiver, filter, portfolio))
+}
+
+/// Forwards price batches to a websocket client, who can replace its symbol
+/// filter at any time by sending `{ "symbols": [...] }` (or `null` for all).
+/// A followed portfolio is sent as `{ "portfolio": ... }` whenever its holdings move.
+async fn stream_price_updates(mut socket: WebSocket, mut receiver: Receiver<Arc<PriceBatch>>, mut filter: SymbolFilter, portfolio: Option<StreamPortfolio>) {
+    if let Some(portfolio) = &portfolio {
+        let payload = json!({ "portfolio": portfolio.json().await });
+        if socket.send(Message::Text(payload.to_string().into())).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            update = receiver.recv() => {
+                match update {
+                    Ok(batch) => {
+                        if let Some(payload) = price_batch_json(&batch, &filter) && socket.send(Message::Text(payload.to_string().into())).await.is_err() {
+                            break;
+                        }
+
+                        let Some(portfolio) = &portfolio else { continue };
+                        let Some(value) = portfolio.json_after(&batch).await else { continue };
+
+                        if socket.send(Message::Text(json!({ "portfolio": value }).to_string().into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!("Websocket client lagging behind, skipped {skipped} price batches"),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<StreamFilterMessage>(&text) {
+                            Ok(request) => filter = SymbolFilter::new(request.symbols),
+                            Err(e) => {
+                                let response = json!({ "error": format!("Invalid filter message: {e}") });
+                                if socket.send(Message::Text(response.to_string().into())).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+async fn list_scheduled_jobs(headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    if let Some(response) = admin_rejection(&headers, &web_state) { return response; }
+
+    let mut last_runs: HashMap<String, JobRun> = get_last_job_runs(web_state.db_pool.clone()).await
+        .into_iter()
+        .map(|run| (run.job_name.clone(), run))
+        .collect();
+
+    let jobs: Vec<_> = web_state.schedules.iter().map(|job| json!({
+        "name": job.name,
+        "cron": job.cron,
+        "timezone": job.timezone,
+        "job": job.job,
+        "next_run": job.next_run(),
+        "last_run": last_runs.remove(&job.name),
+    })).collect();
+
+    Json(json!({ "jobs": jobs })).into_response()
+}
+
+/// Runs a configured job immediately, outside of its schedule.
+async fn run_scheduled_job(headers: HeaderMap, State(web_state): State<ServerState>, Path(name): Path<String>) -> Response {
+    if let Some(response) = admin_rejection(&headers, &web_state) { return response; }
+
+    let Some(job) = web_state.schedules.iter().find(|job| job.name == name) else {
+        return ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No scheduled job named {name}"));
+    };
+
+    let run = run_job(job, &web_state).await;
+
+    Json(run).into_response()
+}
+
+async fn list_finance_subscriptions(headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    if let Some(response) = admin_rejection(&headers, &web_state) { return response; }
+
+    Json(json!({
+        "subscriptions": web_state.finance_state.current_subscriptions().await,
+    })).into_response()
+}
+
+#[derive(Deserialize)]
+struct SubscriptionBody {
+    symbol: String,
+}
+
+async fn create_finance_subscription(headers: HeaderMap, State(web_state): State<ServerState>, Json(body): Json<SubscriptionBody>) -> Response {
+    if let Some(response) = admin_rejection(&headers, &web_state) { return response; }
+
+    let symbol = match normalize_symbol(&body.symbol) {
+        Ok(symbol) => symbol,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    match add_subscription(&web_state.finance_state, &symbol).await {
+        Ok(true) => (StatusCode::CREATED, Json(json!({ "symbol": symbol }))).into_response(),
+        Ok(false) => ErrorCodeResponse::new(StatusCode::CONFLICT, &format!("{symbol} is already subscribed")),
+        Err(e) => ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+}
+
+async fn delete_finance_subscription(Path(symbol): Path<String>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    if let Some(response) = admin_rejection(&headers, &web_state) { return response; }
+
+    match remove_subscription(&web_state.finance_state, &symbol).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("{symbol} is not subscribed")),
+        Err(e) => ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct WatchlistBody {
+    name: String,
+    #[serde(default)]
+    symbols: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct WatchlistUpdateBody {
+    name: Option<String>,
+    symbols: Option<Vec<String>>,
+}
+
+const MAX_WATCHLIST_NAME_LENGTH: usize = 100;
+
+fn watchlist_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_WATCHLIST_NAME_LENGTH {
+        return Err(format!("Watchlist names must be between 1 and {MAX_WATCHLIST_NAME_LENGTH} characters"));
+    }
+
+    Ok(name.to_string())
+}
+
+async fn list_watchlists(headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    Json(json!({
+        "watchlists": get_watchlists(web_state.db_pool, user_id).await,
+    })).into_response()
+}
+
+async fn create_watchlist(headers: HeaderMap, State(web_state): State<ServerState>, Json(body): Json<WatchlistBody>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let name = match watchlist_name(&body.name) {
+        Ok(name) => name,
+        Err(message) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let symbols = match watchlist_symbols(&body.symbols) {
+        Ok(symbols) => symbols,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    if get_watchlists(web_state.db_pool.clone(), user_id.clone()).await.len() >= MAX_WATCHLISTS_PER_USER {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &format!("Users can have at most {MAX_WATCHLISTS_PER_USER} watchlists"));
+    }
+
+    if let Err(e) = ensure_capacity(&web_state.finance_state, &symbols).await {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &e.to_string());
+    }
+
+    let id = Uuid::new_v4();
+    if !insert_watchlist(web_state.db_pool.clone(), id, user_id, name, symbols).await {
+        return ErrorCodeResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the watchlist");
+    }
+
+    sync_subscriptions(&web_state.finance_state).await;
+
+    match get_watchlist(web_state.db_pool, id).await {
+        Some(watchlist) => (StatusCode::CREATED, Json(watchlist)).into_response(),
+        None => ErrorCodeResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read back the watchlist"),
+    }
+}
+
+async fn get_user_watchlist(Path(id): Path<Uuid>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    match get_watchlist(web_state.db_pool, id).await {
+        Some(watchlist) if watchlist.user_id == user_id => Json(watchlist).into_response(),
+        _ => ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No watchlist found for {id}")),
+    }
+}
+
+async fn update_user_watchlist(Path(id): Path<Uuid>, headers: HeaderMap, State(web_state): State<ServerState>, Json(body): Json<WatchlistUpdateBody>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let name = match body.name.as_deref().map(watchlist_name).transpose() {
+        Ok(name) => name,
+        Err(message) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let symbols = match body.symbols.as_deref().map(watchlist_symbols).transpose() {
+        Ok(symbols) => symbols,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    if let Some(symbols) = &symbols && let Err(e) = ensure_capacity(&web_state.finance_state, symbols).await {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &e.to_string());
+    }
+
+    let symbols_changed = symbols.is_some();
+    if !update_watchlist(web_state.db_pool.clone(), id, user_id, name, symbols).await {
+        return ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No watchlist found for {id}"));
+    }
+
+    if symbols_changed {
+        sync_subscriptions(&web_state.finance_state).await;
+    }
+
+    match get_watchlist(web_state.db_pool, id).await {
+        Some(watchlist) => Json(watchlist).into_response(),
+        None => ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No watchlist found for {id}")),
+    }
+}
+
+async fn delete_user_watchlist(Path(id): Path<Uuid>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    if !delete_watchlist(web_state.db_pool, id, user_id).await {
+        return ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No watchlist found for {id}"));
+    }
+
+    sync_subscriptions(&web_state.finance_state).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+struct HoldingBody {
+    quantity: Option<Decimal>,
+    cost_basis: Option<Decimal>,    // Total paid for the quantity
+    lots: Option<Vec<PortfolioLot>>,    // Takes precedence over quantity and cost_basis
+}
+
+async fn user_portfolio(headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let holdings = get_holdings(web_state.db_pool, user_id).await;
+    Json(value_portfolio(&web_state.finance_state.book, holdings).await).into_response()
+}
+
+/// Sets the user's holding in a symbol, replacing any lots recorded before.
+async fn update_user_holding(Path(symbol): Path<String>, headers: HeaderMap, State(web_state): State<ServerState>, Json(body): Json<HoldingBody>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let symbol = match normalize_symbol(&symbol) {
+        Ok(symbol) => symbol,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let lots = match (body.lots, body.quantity, body.cost_basis) {
+        (Some(lots), _, _) => lots,
+        (None, Some(quantity), Some(cost_basis)) => vec![PortfolioLot { quantity, cost_basis, acquired_on: None }],
+        _ => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, "Holdings need either lots or a quantity and cost_basis"),
+    };
+
+    let lots = match holding_lots(lots) {
+        Ok(lots) => lots,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let holdings = get_holdings(web_state.db_pool.clone(), user_id.clone()).await;
+    if holdings.len() >= MAX_PORTFOLIO_HOLDINGS && !holdings.iter().any(|holding| holding.symbol == symbol) {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &format!("Portfolios hold at most {MAX_PORTFOLIO_HOLDINGS} symbols"));
+    }
+
+    if let Err(e) = ensure_capacity(&web_state.finance_state, std::slice::from_ref(&symbol)).await {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &e.to_string());
+    }
+
+    if !replace_holding(web_state.db_pool.clone(), user_id.clone(), symbol, lots).await {
+        return ErrorCodeResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store the holding");
+    }
+
+    sync_subscriptions(&web_state.finance_state).await;
+
+    let holdings = get_holdings(web_state.db_pool, user_id).await;
+    Json(value_portfolio(&web_state.finance_state.book, holdings).await).into_response()
+}
+
+async fn delete_user_holding(Path(symbol): Path<String>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let symbol = match normalize_symbol(&symbol) {
+        Ok(symbol) => symbol,
+        Err(e) => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    if !delete_holding(web_state.db_pool, user_id, symbol.clone()).await {
+        return ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No holding found for {symbol}"));
+    }
+
+    sync_subscriptions(&web_state.finance_state).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+struct AlertBody {
+    symbol: String,
+    condition: AlertCondition,
+    cooldown_secs: Option<i32>,
+}
+
+async fn list_user_alerts(headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    Json(json!({
+        "alerts": list_alerts(&web_state.finance_state, user_id).await,
+    })).into_response()
+}
+
+async fn create_user_alert(headers: HeaderMap, State(web_state): State<ServerState>, Json(body): Json<AlertBody>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    if list_alerts(&web_state.finance_state, user_id.clone()).await.len() >= MAX_ALERTS_PER_USER {
+        return ErrorCodeResponse::new(StatusCode::CONFLICT, &format!("Users can have at most {MAX_ALERTS_PER_USER} alerts"));
+    }
+
+    match create_alert(&web_state.finance_state, user_id, &body.symbol, body.condition, body.cooldown_secs).await {
+        Ok(alert) => (StatusCode::CREATED, Json(alert)).into_response(),
+        Err(e) => ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+}
+
+async fn delete_user_alert(Path(id): Path<Uuid>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    if !delete_alert(&web_state.finance_state, id, user_id).await {
+        return ErrorCodeResponse::new(StatusCode::NOT_FOUND, &format!("No alert found for {id}"));
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+struct AlertHistoryQuery {
+    limit: Option<i64>,
+}
+
+const MAX_ALERT_HISTORY: i64 = 500;
+
+async fn user_alert_history(Query(query): Query<AlertHistoryQuery>, headers: HeaderMap, State(web_state): State<ServerState>) -> Response {
+    let user_id = match authenticated_user(&headers, &web_state) {
+        Ok(user_id) => user_id,
+        Err((status, message)) => return ErrorCodeResponse::new(status, &message),
+    };
+
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_ALERT_HISTORY);
+
+    Json(json!({
+        "events": get_alert_events(web_state.db_pool, user_id, limit).await,
+    })).into_response()
+}
+
+async fn finance_movers(State(web_state): State<ServerState>) -> impl IntoResponse {
+    Json(web_state.finance_state.movers.summary().await)
+}
+
+#[derive(Deserialize)]
+struct NewsQuery {
+    symbol: Option<String>,
+    limit: Option<i64>,
+}
+
+const MAX_NEWS: i64 = 200;
+
+async fn finance_news(Query(query): Query<NewsQuery>, State(web_state): State<ServerState>) -> Response {
+    let symbol = query.symbol.map(|symbol| symbol.trim().to_uppercase()).filter(|symbol| !symbol.is_empty());
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_NEWS);
+
+    Json(json!({
+        "news": get_news(web_state.db_pool, symbol, limit).await,
+    })).into_response()
+}
+
+#[derive(Deserialize)]
+struct CandleQuery {
+    interval: Option<CandleInterval>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+const MAX_CANDLES: i64 = 5_000;
+
+async fn finance_candles(Path(symbol): Path<String>, Query(query): Query<CandleQuery>, State(web_state): State<ServerState>) -> Response {
+    let interval = query.interval.unwrap_or(CandleInterval::OneMinute);
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = match query.from.or_else(|| to.checked_sub_signed(interval.duration() * 240)) {
+        Some(from) => from,
+        None => return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, "`to` is too early to go back 240 intervals from"),
+    };
+
+    if from >= to {
+        return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, "`from` must be earlier than `to`");
+    }
+
+    let requested_candles = (to - from).num_seconds() / interval.duration().num_seconds();
+    if requested_candles > MAX_CANDLES {
+        return ErrorCodeResponse::new(StatusCode::BAD_REQUEST, &format!("Requested range spans more than {MAX_CANDLES} candles, use a larger interval"));
+    }
+
+    let candles = get_candles(web_state.db_pool, symbol.to_uppercase(), interval, from, to).await;
 
-    Json(health)
+    Json(json!({
+        "symbol": symbol.to_uppercase(),
+        "candles": candles,
+    })).into_response()
 }
 
 async fn team_matchups(Path(team_key): Path<String>, jar: CookieJar, State(web_state): State<ServerState>, headers: HeaderMap, refresh_token: Option<Json<RefreshBody>>) -> Response {
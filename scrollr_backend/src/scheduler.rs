@@ -0,0 +1,191 @@
+use std::{fs, str::FromStr, sync::Arc, time::Instant};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use finance_service::{refresh_news, refresh_symbol_metadata, update_all_previous_closes};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use sports_service::frequent_poll;
+use tokio::time::sleep;
+use utils::{database::{finance::delete_trade_history_before, news::delete_news_before, scheduler::{JobRun, create_tables, delete_job_runs_before, insert_job_run}, sports::LeagueConfigs}, log::{error, info, warn}};
+
+use crate::ServerState;
+
+const SCHEDULES_CONFIG_PATH: &str = "./configs/schedules.json";
+const LEAGUES_CONFIG_PATH: &str = "./configs/leagues.json";
+
+/// Work a schedule entry can trigger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    FinancePreviousCloses,
+    /// Re-fetches symbol metadata older than `max_age_days`, and any that is missing.
+    FinanceMetadata {
+        max_age_days: u32,
+    },
+    /// Pulls general market news and company news of the next `symbols_per_run` subscribed stocks.
+    FinanceNews {
+        symbols_per_run: usize,
+    },
+    SportsPoll {
+        leagues: Vec<String>,
+    },
+    /// Prunes tables that otherwise grow forever, along with expired CSRF tokens.
+    Cleanup {
+        trade_history_days: u32,
+        job_run_days: u32,
+        news_days: u32,
+    },
+}
+
+/// One entry of `configs/schedules.json`.
+#[derive(Debug, Deserialize)]
+struct ScheduleConfig {
+    name: String,
+    /// Seconds, minutes, hours, day of month, month, day of week, e.g. `0 */5 * * * *`.
+    cron: String,
+    /// IANA timezone the cron expression is evaluated in, defaults to UTC.
+    #[serde(default)]
+    timezone: Option<String>,
+    job: Job,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub job: Job,
+    #[serde(skip)]
+    schedule: Schedule,
+    #[serde(skip)]
+    tz: Tz,
+}
+
+impl ScheduledJob {
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.schedule.upcoming(self.tz).next().map(|at| at.with_timezone(&Utc))
+    }
+}
+
+/// Reads `configs/schedules.json`, an invalid entry is a startup error rather than a job that silently never runs.
+pub fn load_schedules() -> Vec<ScheduledJob> {
+    let contents = match fs::read_to_string(SCHEDULES_CONFIG_PATH) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Could not read {SCHEDULES_CONFIG_PATH} ({e}), no jobs will be scheduled");
+            return Vec::new();
+        }
+    };
+
+    let configs: Vec<ScheduleConfig> = serde_json::from_str(&contents).expect("Failed parsing schedule configs as Json");
+
+    configs.into_iter().map(|config| {
+        let schedule = Schedule::from_str(&config.cron)
+            .unwrap_or_else(|e| panic!("Invalid cron expression for job {}: {e}", config.name));
+
+        let timezone = config.timezone.unwrap_or_else(|| String::from("UTC"));
+        let tz = timezone.parse::<Tz>()
+            .unwrap_or_else(|e| panic!("Invalid timezone for job {}: {e}", config.name));
+
+        ScheduledJob { name: config.name, cron: config.cron, timezone, job: config.job, schedule, tz }
+    }).collect()
+}
+
+/// Runs every configured job on its schedule for the lifetime of the server.
+pub async fn start_scheduler(state: ServerState) {
+    info!("Starting scheduler with {} jobs...", state.schedules.len());
+    create_tables(state.db_pool.clone()).await;
+
+    let schedules = Arc::clone(&state.schedules);
+    let loops: Vec<_> = schedules.iter().map(|job| run_schedule(job, state.clone())).collect();
+
+    join_all(loops).await;
+}
+
+async fn run_schedule(job: &ScheduledJob, state: ServerState) {
+    for next in job.schedule.upcoming(job.tz) {
+        let next = next.with_timezone(&Utc);
+        let now = Utc::now();
+
+        // A run that took longer than the interval skips the slots it overlapped.
+        if next < now {
+            continue;
+        }
+
+        info!("Next run of {} at {next}", job.name);
+        sleep((next - now).to_std().unwrap_or_default()).await;
+
+        run_job(job, &state).await;
+    }
+
+    warn!("Job {} has no upcoming runs", job.name);
+}
+
+/// Executes a job once and records the outcome, panics count as failures.
+pub async fn run_job(job: &ScheduledJob, state: &ServerState) -> JobRun {
+    info!("Running scheduled job {}...", job.name);
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+
+    let error = match tokio::spawn(execute(job.job.clone(), state.clone())).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{e:#}")),
+        Err(e) => Some(format!("Job panicked: {e}")),
+    };
+
+    let run = JobRun {
+        job_name: job.name.clone(),
+        started_at,
+        duration_ms: start.elapsed().as_millis() as i64,
+        outcome: String::from(if error.is_none() { "success" } else { "failure" }),
+        error,
+    };
+
+    match &run.error {
+        None => info!("Job {} completed in {}ms", run.job_name, run.duration_ms),
+        Some(e) => error!("Job {} failed after {}ms: {e}", run.job_name, run.duration_ms),
+    }
+
+    insert_job_run(state.db_pool.clone(), run.clone()).await;
+    run
+}
+
+async fn execute(job: Job, state: ServerState) -> Result<()> {
+    match job {
+        Job::FinancePreviousCloses => update_all_previous_closes(state.finance_state).await,
+        Job::FinanceMetadata { max_age_days } => refresh_symbol_metadata(state.finance_state, Some(chrono::Duration::days(max_age_days.into()))).await,
+        Job::FinanceNews { symbols_per_run } => refresh_news(state.finance_state, symbols_per_run).await,
+        Job::SportsPoll { leagues } => {
+            let configs = load_league_configs(&leagues)?;
+            if configs.is_empty() {
+                bail!("None of {leagues:?} are configured in {LEAGUES_CONFIG_PATH}");
+            }
+
+            frequent_poll(configs, &state.db_pool).await;
+        }
+        Job::Cleanup { trade_history_days, job_run_days, news_days } => {
+            let now = Utc::now();
+
+            let trades = delete_trade_history_before(state.db_pool.clone(), now - chrono::Duration::days(trade_history_days.into())).await;
+            let runs = delete_job_runs_before(state.db_pool.clone(), now - chrono::Duration::days(job_run_days.into())).await;
+            let news = delete_news_before(state.db_pool.clone(), now - chrono::Duration::days(news_days.into())).await;
+            state.cleanup_expired_csrf_tokens().await;
+
+            info!("Cleanup removed {trades} trade history rows, {runs} job runs and {news} headlines");
+        }
+    }
+
+    Ok(())
+}
+
+/// Configs for the named leagues from `configs/leagues.json`.
+pub fn load_league_configs(names: &[String]) -> Result<Vec<LeagueConfigs>> {
+    let contents = fs::read_to_string(LEAGUES_CONFIG_PATH).context("Failed to read leagues config file")?;
+    let leagues: Vec<LeagueConfigs> = serde_json::from_str(&contents).context("Failed to parse leagues config JSON")?;
+
+    Ok(leagues.into_iter().filter(|league| names.contains(&league.name)).collect())
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use log::error;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, query, query_as};
+use uuid::Uuid;
+
+/// A stored alert rule, `threshold` is unused by kinds that do not take one.
+#[derive(FromRow, Debug, Clone)]
+pub struct AlertRuleRow {
+    pub id: Uuid,
+    pub user_id: String,
+    pub symbol: String,
+    pub kind: String,
+    pub threshold: Option<Decimal>,
+    pub cooldown_secs: i32,
+    pub last_triggered_at: Option<chrono::DateTime<Utc>>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+/// One triggered alert and the outcome of its webhook delivery.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct AlertEvent {
+    pub id: i64,
+    pub rule_id: Uuid,
+    #[serde(skip)]
+    pub user_id: String,
+    pub symbol: String,
+    pub kind: String,
+    pub price: Decimal,
+    pub percentage_change: Decimal,
+    pub message: String,
+    pub triggered_at: chrono::DateTime<Utc>,
+    pub delivered: bool,
+    pub delivery_error: Option<String>,
+}
+
+pub async fn create_tables(pool: Arc<PgPool>) {
+    let rules_statement = "
+        CREATE TABLE IF NOT EXISTS alert_rules (
+            id UUID PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            symbol VARCHAR(30) NOT NULL,
+            kind VARCHAR(20) NOT NULL,
+            threshold NUMERIC,
+            cooldown_secs INTEGER NOT NULL,
+            last_triggered_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+    ";
+
+    let rules_index_statement = "
+        CREATE INDEX IF NOT EXISTS alert_rules_user_id_idx
+            ON alert_rules (user_id);
+    ";
+
+    // Events outlive their rule, so the history stays queryable after a rule is deleted.
+    let events_statement = "
+        CREATE TABLE IF NOT EXISTS alert_events (
+            id BIGSERIAL PRIMARY KEY,
+            rule_id UUID NOT NULL,
+            user_id TEXT NOT NULL,
+            symbol VARCHAR(30) NOT NULL,
+            kind VARCHAR(20) NOT NULL,
+            price NUMERIC NOT NULL,
+            percentage_change NUMERIC NOT NULL,
+            message TEXT NOT NULL,
+            triggered_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            delivered BOOLEAN NOT NULL,
+            delivery_error TEXT
+        );
+    ";
+
+    let events_index_statement = "
+        CREATE INDEX IF NOT EXISTS alert_events_user_id_triggered_at_idx
+            ON alert_events (user_id, triggered_at DESC);
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        for statement in [rules_statement, rules_index_statement, events_statement, events_index_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// Returns `false` if the rule could not be stored.
+pub async fn insert_alert_rule(pool: Arc<PgPool>, rule: AlertRuleRow) -> bool {
+    let statement = "
+        INSERT INTO alert_rules (id, user_id, symbol, kind, threshold, cooldown_secs, last_triggered_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(rule.id)
+            .bind(rule.user_id)
+            .bind(rule.symbol)
+            .bind(rule.kind)
+            .bind(rule.threshold)
+            .bind(rule.cooldown_secs)
+            .bind(rule.last_triggered_at)
+            .bind(rule.created_at)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        false
+    }
+}
+
+/// Every user's rules, or only those of `user_id`.
+pub async fn get_alert_rules(pool: Arc<PgPool>, user_id: Option<String>) -> Vec<AlertRuleRow> {
+    let statement = "
+        SELECT id, user_id, symbol, kind, threshold, cooldown_secs, last_triggered_at, created_at
+        FROM alert_rules
+        WHERE $1::TEXT IS NULL OR user_id = $1
+        ORDER BY created_at ASC
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(user_id)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Returns `true` if the rule existed and belonged to `user_id`.
+pub async fn delete_alert_rule(pool: Arc<PgPool>, id: Uuid, user_id: String) -> bool {
+    let statement = "
+        DELETE FROM alert_rules
+            WHERE id = $1 AND user_id = $2
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok_and(|result| result.rows_affected() > 0)
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        false
+    }
+}
+
+pub async fn set_alert_triggered(pool: Arc<PgPool>, id: Uuid, triggered_at: chrono::DateTime<Utc>) {
+    let statement = "
+        UPDATE alert_rules
+            SET last_triggered_at = $2
+        WHERE id = $1
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        let _ = query(statement)
+            .bind(id)
+            .bind(triggered_at)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e));
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// `id` is assigned by the database and ignored here.
+pub async fn insert_alert_event(pool: Arc<PgPool>, event: AlertEvent) {
+    let statement = "
+        INSERT INTO alert_events (rule_id, user_id, symbol, kind, price, percentage_change, message, triggered_at, delivered, delivery_error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        let _ = query(statement)
+            .bind(event.rule_id)
+            .bind(event.user_id)
+            .bind(event.symbol)
+            .bind(event.kind)
+            .bind(event.price)
+            .bind(event.percentage_change)
+            .bind(event.message)
+            .bind(event.triggered_at)
+            .bind(event.delivered)
+            .bind(event.delivery_error)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e));
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// The user's most recent alerts first.
+pub async fn get_alert_events(pool: Arc<PgPool>, user_id: String, limit: i64) -> Vec<AlertEvent> {
+    let statement = "
+        SELECT id, rule_id, user_id, symbol, kind, price, percentage_change, message, triggered_at, delivered, delivery_error
+        FROM alert_events
+        WHERE user_id = $1
+        ORDER BY triggered_at DESC
+        LIMIT $2
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(user_id)
+            .bind(limit)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
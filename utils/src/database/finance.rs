@@ -1,42 +1,205 @@
 use std::sync::Arc;
 
-pub use chrono::Utc;
+pub use chrono::{NaiveDate, Utc};
+pub use rust_decimal::Decimal;
 use log::error;
+use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool, query, query_as};
 
 #[derive(FromRow, Clone)]
 pub struct DatabaseTradeData {
     pub symbol: String, 
-    pub price: f64, 
-    pub previous_close: f64, 
-    pub price_change: f64,
-    pub percentage_change: f64,
+    pub price: Decimal,
+    pub previous_close: Decimal,
+    pub price_change: Decimal,
+    pub percentage_change: Decimal,
     pub direction: String,
-    pub last_updated: chrono::DateTime<Utc>
+    pub last_updated: chrono::DateTime<Utc>,
+    /// Decimal places the symbol is quoted with, at least two.
+    pub price_scale: i16,
+    /// Trading day the stored previous close is the reference for.
+    pub session_date: Option<NaiveDate>,
+    /// Range and volume of `session_date`, the range is `None` until its first trade.
+    pub day_open: Option<Decimal>,
+    pub day_high: Option<Decimal>,
+    pub day_low: Option<Decimal>,
+    pub day_volume: Decimal,
+}
+
+/// One processed trade, folded into the price book and its history.
+#[derive(Debug, Clone)]
+pub struct TradeBatchRow {
+    pub symbol: String,
+    pub price: Decimal,
+    pub previous_close: Decimal,
+    pub price_change: Decimal,
+    pub percentage_change: Decimal,
+    pub direction: String,
+    pub session_date: NaiveDate,
+    pub price_scale: i16,
+    /// Range, volume and count of every trade coalesced into this row.
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub volume: Decimal,
+    pub trade_count: i32,
+    pub traded_at: chrono::DateTime<Utc>,
+}
+
+/// Reference data for a symbol, refreshed from the provider's company profiles.
+#[derive(FromRow, Debug, Clone)]
+pub struct SymbolMetadata {
+    pub symbol: String,
+    pub name: Option<String>,
+    pub logo_url: Option<String>,
+    pub exchange: Option<String>,
+    pub currency: Option<String>,
+    pub asset_class: String,
+    pub sector: Option<String>,
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize, Clone)]
+pub struct Candle {
+    pub time: chrono::DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub trade_count: i64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            CandleInterval::OneMinute => chrono::Duration::minutes(1),
+            CandleInterval::FiveMinutes => chrono::Duration::minutes(5),
+            CandleInterval::OneHour => chrono::Duration::hours(1),
+            CandleInterval::OneDay => chrono::Duration::days(1),
+        }
+    }
+
+    fn as_pg_interval(&self) -> &'static str {
+        match self {
+            CandleInterval::OneMinute => "1 minute",
+            CandleInterval::FiveMinutes => "5 minutes",
+            CandleInterval::OneHour => "1 hour",
+            CandleInterval::OneDay => "1 day",
+        }
+    }
 }
 
 pub async fn create_tables(pool: Arc<PgPool>) {
-    let statement = "
+    let trades_statement = "
         CREATE TABLE IF NOT EXISTS trades (
             id SERIAL PRIMARY KEY,
             symbol VARCHAR(30) UNIQUE NOT NULL,
-            price DECIMAL(10,2),
-            previous_close DECIMAL(10,2),
-            price_change DECIMAL(10,2),
-            percentage_change DECIMAL(5,2),
+            price NUMERIC,
+            previous_close NUMERIC,
+            price_change NUMERIC,
+            percentage_change NUMERIC,
             direction VARCHAR(10),
             last_updated TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
             created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
         );
     ";
 
+    let history_statement = "
+        CREATE TABLE IF NOT EXISTS trade_history (
+            id BIGSERIAL PRIMARY KEY,
+            symbol VARCHAR(30) NOT NULL,
+            price NUMERIC NOT NULL,
+            volume NUMERIC NOT NULL DEFAULT 0,
+            traded_at TIMESTAMP WITH TIME ZONE NOT NULL
+        );
+    ";
+
+    let history_index_statement = "
+        CREATE INDEX IF NOT EXISTS trade_history_symbol_traded_at_idx
+            ON trade_history (symbol, traded_at);
+    ";
+
+    // Added after the table shipped, so existing deployments need the column too.
+    let session_date_statement = "
+        ALTER TABLE trades ADD COLUMN IF NOT EXISTS session_date DATE;
+    ";
+
+    // The original fixed scale columns rounded sub-cent prices and overflowed on large moves,
+    // dropping the typmod is a metadata only change for existing rows.
+    let trades_precision_statement = "
+        ALTER TABLE trades
+            ALTER COLUMN price TYPE NUMERIC,
+            ALTER COLUMN previous_close TYPE NUMERIC,
+            ALTER COLUMN price_change TYPE NUMERIC,
+            ALTER COLUMN percentage_change TYPE NUMERIC,
+            ADD COLUMN IF NOT EXISTS price_scale SMALLINT NOT NULL DEFAULT 2;
+    ";
+
+    let day_range_statement = "
+        ALTER TABLE trades
+            ADD COLUMN IF NOT EXISTS day_open NUMERIC,
+            ADD COLUMN IF NOT EXISTS day_high NUMERIC,
+            ADD COLUMN IF NOT EXISTS day_low NUMERIC,
+            ADD COLUMN IF NOT EXISTS day_volume NUMERIC NOT NULL DEFAULT 0;
+    ";
+
+    let history_precision_statement = "
+        ALTER TABLE trade_history
+            ALTER COLUMN price TYPE NUMERIC,
+            ALTER COLUMN volume TYPE NUMERIC;
+    ";
+
+    // One history row covers every trade of a batch, older rows are single trades without a range.
+    let history_range_statement = "
+        ALTER TABLE trade_history
+            ADD COLUMN IF NOT EXISTS open NUMERIC,
+            ADD COLUMN IF NOT EXISTS high NUMERIC,
+            ADD COLUMN IF NOT EXISTS low NUMERIC,
+            ADD COLUMN IF NOT EXISTS trade_count INTEGER NOT NULL DEFAULT 1;
+    ";
+
+    let subscriptions_statement = "
+        CREATE TABLE IF NOT EXISTS finance_subscriptions (
+            symbol VARCHAR(30) PRIMARY KEY,
+            created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        );
+    ";
+
+    let metadata_statement = "
+        CREATE TABLE IF NOT EXISTS symbol_metadata (
+            symbol VARCHAR(30) PRIMARY KEY,
+            name TEXT,
+            logo_url TEXT,
+            exchange TEXT,
+            currency VARCHAR(10),
+            asset_class VARCHAR(10) NOT NULL,
+            sector TEXT,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+    ";
+
     let conn = pool.acquire().await;
 
     if let Ok(mut connection) = conn {
-        let _ = query(statement)
-            .execute(&mut *connection)
-            .await
-            .inspect_err(|e| error!("Execution Error: {}", e));
+        for statement in [trades_statement, session_date_statement, trades_precision_statement, day_range_statement, history_statement, history_precision_statement, history_range_statement, history_index_statement, subscriptions_statement, metadata_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
     } else {
         error!("Connection Error: Failed to acquire a connection from the pool");
     }
@@ -62,52 +225,66 @@ pub async fn insert_symbol(pool: Arc<PgPool>, symbol: String) {
     }
 }
 
-pub async fn update_previous_close(pool: Arc<PgPool>, symbol: String, prev_close: f64) {
+pub async fn get_subscriptions(pool: Arc<PgPool>) -> Result<Vec<String>, sqlx::Error> {
+    let statement = "
+        SELECT symbol
+        FROM finance_subscriptions
+        ORDER BY created_at ASC, symbol ASC
+    ";
+
+    let mut connection = pool.acquire().await
+        .inspect_err(|_| error!("Connection Error: Failed to acquire a connection from the pool"))?;
+
+    let rows: Vec<(String,)> = query_as(statement)
+        .fetch_all(&mut *connection)
+        .await
+        .inspect_err(|e| error!("Execution Error: {}", e))?;
+
+    Ok(rows.into_iter().map(|(symbol,)| symbol).collect())
+}
+
+/// Returns `true` if the symbol was not already subscribed.
+pub async fn insert_subscription(pool: Arc<PgPool>, symbol: String) -> bool {
     let statement = "
-        UPDATE trades
-            SET previous_close = $1
-            WHERE symbol = $2
+        INSERT INTO finance_subscriptions (symbol)
+            VALUES ($1)
+        ON CONFLICT (symbol) DO NOTHING
     ";
 
     let conn = pool.acquire().await;
 
     if let Ok(mut connection) = conn {
-        let _ = query(statement)
-            .bind(prev_close)
+        query(statement)
             .bind(symbol)
             .execute(&mut *connection)
             .await
-            .inspect_err(|e| error!("Execution Error: {}", e));
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok_and(|result| result.rows_affected() > 0)
     } else {
         error!("Connection Error: Failed to acquire a connection from the pool");
+        false
     }
 }
 
-pub async fn update_trade(pool: Arc<PgPool>, symbol: String, price: f64, price_change: f64, percentage_change: f64, direction: &str) {
+/// Returns `true` if the symbol was subscribed.
+pub async fn delete_subscription(pool: Arc<PgPool>, symbol: String) -> bool {
     let statement = "
-        UPDATE trades
-            SET price = $1,
-                price_change = $2,
-                percentage_change = $3,
-                direction = $4,
-                last_updated = CURRENT_TIMESTAMP
-            WHERE symbol = $5
+        DELETE FROM finance_subscriptions
+            WHERE symbol = $1
     ";
 
     let conn = pool.acquire().await;
 
     if let Ok(mut connection) = conn {
-        let _ = query(statement)
-            .bind(price)
-            .bind(price_change)
-            .bind(percentage_change)
-            .bind(direction)
+        query(statement)
             .bind(symbol)
             .execute(&mut *connection)
             .await
-            .inspect_err(|e| error!("Execution Error: {}", e));
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok_and(|result| result.rows_affected() > 0)
     } else {
         error!("Connection Error: Failed to acquire a connection from the pool");
+        false
     }
 }
 
@@ -115,12 +292,18 @@ pub async fn get_trades(pool: Arc<PgPool>) -> Vec<DatabaseTradeData> {
     let statement = "
         SELECT
             symbol,
-            price::FLOAT8 as price,
-            previous_close::FLOAT8 as previous_close,
-            price_change::FLOAT8 as price_change,
-            percentage_change::FLOAT8 as percentage_change,
-            direction,
-            last_updated
+            COALESCE(price, 0) as price,
+            COALESCE(previous_close, 0) as previous_close,
+            COALESCE(price_change, 0) as price_change,
+            COALESCE(percentage_change, 0) as percentage_change,
+            COALESCE(direction, 'up') as direction,
+            last_updated,
+            session_date,
+            price_scale,
+            day_open,
+            day_high,
+            day_low,
+            day_volume
         FROM trades
         ORDER BY symbol ASC
     ";
@@ -142,4 +325,375 @@ pub async fn get_trades(pool: Arc<PgPool>) -> Vec<DatabaseTradeData> {
         error!("Connection Error: Failed to acquire a connection from the pool");
         return Vec::new();
     }
-}
\ No newline at end of file
+}
+
+pub async fn get_trade(pool: Arc<PgPool>, symbol: String) -> Option<DatabaseTradeData> {
+    let statement = "
+        SELECT
+            symbol,
+            COALESCE(price, 0) as price,
+            COALESCE(previous_close, 0) as previous_close,
+            COALESCE(price_change, 0) as price_change,
+            COALESCE(percentage_change, 0) as percentage_change,
+            COALESCE(direction, 'up') as direction,
+            last_updated,
+            session_date,
+            price_scale,
+            day_open,
+            day_high,
+            day_low,
+            day_volume
+        FROM trades
+        WHERE symbol = $1
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(symbol)
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .ok()
+            .flatten()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        None
+    }
+}
+
+/// Rows for just the given symbols, unknown symbols are left out.
+pub async fn get_trades_for(pool: Arc<PgPool>, symbols: Vec<String>) -> Vec<DatabaseTradeData> {
+    let statement = "
+        SELECT
+            symbol,
+            COALESCE(price, 0) as price,
+            COALESCE(previous_close, 0) as previous_close,
+            COALESCE(price_change, 0) as price_change,
+            COALESCE(percentage_change, 0) as percentage_change,
+            COALESCE(direction, 'up') as direction,
+            last_updated,
+            session_date,
+            price_scale,
+            day_open,
+            day_high,
+            day_low,
+            day_volume
+        FROM trades
+        WHERE symbol = ANY($1)
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(symbols)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+pub async fn upsert_symbol_metadata(pool: Arc<PgPool>, metadata: SymbolMetadata) {
+    let statement = "
+        INSERT INTO symbol_metadata (symbol, name, logo_url, exchange, currency, asset_class, sector, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (symbol) DO UPDATE SET
+            name = EXCLUDED.name,
+            logo_url = EXCLUDED.logo_url,
+            exchange = EXCLUDED.exchange,
+            currency = EXCLUDED.currency,
+            asset_class = EXCLUDED.asset_class,
+            sector = EXCLUDED.sector,
+            updated_at = EXCLUDED.updated_at
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        let _ = query(statement)
+            .bind(metadata.symbol)
+            .bind(metadata.name)
+            .bind(metadata.logo_url)
+            .bind(metadata.exchange)
+            .bind(metadata.currency)
+            .bind(metadata.asset_class)
+            .bind(metadata.sector)
+            .bind(metadata.updated_at)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e));
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// Metadata for just the given symbols, symbols that were never fetched are left out.
+pub async fn get_symbol_metadata(pool: Arc<PgPool>, symbols: Vec<String>) -> Vec<SymbolMetadata> {
+    let statement = "
+        SELECT symbol, name, logo_url, exchange, currency, asset_class, sector, updated_at
+        FROM symbol_metadata
+        WHERE symbol = ANY($1)
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(symbols)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Trades appended to `trade_history` as one row, candles are built from these.
+/// `price` is the last of the trades and `traded_at` its time.
+#[derive(Debug, Clone)]
+pub struct TradeHistoryRow {
+    pub symbol: String,
+    pub price: Decimal,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub volume: Decimal,
+    pub trade_count: i32,
+    pub traded_at: chrono::DateTime<Utc>,
+}
+
+/// Writes the rows as they are, deletes the removed symbols and appends the history, all in one transaction.
+pub async fn store_trades(pool: Arc<PgPool>, rows: Vec<DatabaseTradeData>, removed: Vec<String>, history: Vec<TradeHistoryRow>) -> bool {
+    let trades_statement = "
+        INSERT INTO trades (symbol, price, previous_close, price_change, percentage_change, direction, session_date, price_scale, day_open, day_high, day_low, day_volume, last_updated)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::NUMERIC[], $3::NUMERIC[], $4::NUMERIC[], $5::NUMERIC[], $6::VARCHAR[], $7::DATE[], $8::SMALLINT[], $9::NUMERIC[], $10::NUMERIC[], $11::NUMERIC[], $12::NUMERIC[], $13::TIMESTAMPTZ[])
+        ON CONFLICT (symbol) DO UPDATE
+            SET price = EXCLUDED.price,
+                previous_close = EXCLUDED.previous_close,
+                price_change = EXCLUDED.price_change,
+                percentage_change = EXCLUDED.percentage_change,
+                direction = EXCLUDED.direction,
+                session_date = EXCLUDED.session_date,
+                price_scale = EXCLUDED.price_scale,
+                day_open = EXCLUDED.day_open,
+                day_high = EXCLUDED.day_high,
+                day_low = EXCLUDED.day_low,
+                day_volume = EXCLUDED.day_volume,
+                last_updated = EXCLUDED.last_updated
+    ";
+
+    let delete_statement = "
+        DELETE FROM trades
+            WHERE symbol = ANY($1)
+    ";
+
+    let history_statement = "
+        INSERT INTO trade_history (symbol, price, open, high, low, volume, trade_count, traded_at)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::NUMERIC[], $3::NUMERIC[], $4::NUMERIC[], $5::NUMERIC[], $6::NUMERIC[], $7::INTEGER[], $8::TIMESTAMPTZ[])
+    ";
+
+    let mut symbols = Vec::with_capacity(rows.len());
+    let mut prices = Vec::with_capacity(rows.len());
+    let mut previous_closes = Vec::with_capacity(rows.len());
+    let mut price_changes = Vec::with_capacity(rows.len());
+    let mut percentage_changes = Vec::with_capacity(rows.len());
+    let mut directions = Vec::with_capacity(rows.len());
+    let mut session_dates = Vec::with_capacity(rows.len());
+    let mut price_scales = Vec::with_capacity(rows.len());
+    let mut opens = Vec::with_capacity(rows.len());
+    let mut highs = Vec::with_capacity(rows.len());
+    let mut lows = Vec::with_capacity(rows.len());
+    let mut volumes = Vec::with_capacity(rows.len());
+    let mut updated_ats = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        symbols.push(row.symbol);
+        prices.push(row.price);
+        previous_closes.push(row.previous_close);
+        price_changes.push(row.price_change);
+        percentage_changes.push(row.percentage_change);
+        directions.push(row.direction);
+        session_dates.push(row.session_date);
+        price_scales.push(row.price_scale);
+        opens.push(row.day_open);
+        highs.push(row.day_high);
+        lows.push(row.day_low);
+        volumes.push(row.day_volume);
+        updated_ats.push(row.last_updated);
+    }
+
+    let mut history_symbols = Vec::with_capacity(history.len());
+    let mut history_prices = Vec::with_capacity(history.len());
+    let mut history_opens = Vec::with_capacity(history.len());
+    let mut history_highs = Vec::with_capacity(history.len());
+    let mut history_lows = Vec::with_capacity(history.len());
+    let mut history_volumes = Vec::with_capacity(history.len());
+    let mut trade_counts = Vec::with_capacity(history.len());
+    let mut traded_ats = Vec::with_capacity(history.len());
+
+    for row in history {
+        history_symbols.push(row.symbol);
+        history_prices.push(row.price);
+        history_opens.push(row.open);
+        history_highs.push(row.high);
+        history_lows.push(row.low);
+        history_volumes.push(row.volume);
+        trade_counts.push(row.trade_count);
+        traded_ats.push(row.traded_at);
+    }
+
+    let Ok(mut transaction) = pool.begin().await else {
+        error!("Connection Error: Failed to begin a transaction");
+        return false;
+    };
+
+    let trades_result = query(trades_statement)
+        .bind(symbols)
+        .bind(prices)
+        .bind(previous_closes)
+        .bind(price_changes)
+        .bind(percentage_changes)
+        .bind(directions)
+        .bind(session_dates)
+        .bind(price_scales)
+        .bind(opens)
+        .bind(highs)
+        .bind(lows)
+        .bind(volumes)
+        .bind(updated_ats)
+        .execute(&mut *transaction)
+        .await;
+
+    let delete_result = match trades_result {
+        Ok(_) => query(delete_statement)
+            .bind(removed)
+            .execute(&mut *transaction)
+            .await,
+        Err(e) => Err(e),
+    };
+
+    let history_result = match delete_result {
+        Ok(_) => query(history_statement)
+            .bind(history_symbols)
+            .bind(history_prices)
+            .bind(history_opens)
+            .bind(history_highs)
+            .bind(history_lows)
+            .bind(history_volumes)
+            .bind(trade_counts)
+            .bind(traded_ats)
+            .execute(&mut *transaction)
+            .await,
+        Err(e) => Err(e),
+    };
+
+    match history_result {
+        Ok(_) => transaction.commit().await.inspect_err(|e| error!("Execution Error: {}", e)).is_ok(),
+        Err(e) => {
+            error!("Execution Error: {}", e);
+            // Dropping the transaction rolls it back.
+            false
+        }
+    }
+}
+
+/// Last traded price of each symbol at `at`, only trades after `not_before` count.
+pub async fn get_prices_at(pool: Arc<PgPool>, symbols: Vec<String>, at: chrono::DateTime<Utc>, not_before: chrono::DateTime<Utc>) -> Vec<(String, Decimal)> {
+    let statement = "
+        SELECT symbols.symbol, last_trade.price
+        FROM UNNEST($1::VARCHAR[]) AS symbols (symbol)
+        CROSS JOIN LATERAL (
+            SELECT price
+            FROM trade_history
+            WHERE trade_history.symbol = symbols.symbol AND traded_at <= $2 AND traded_at > $3
+            ORDER BY traded_at DESC
+            LIMIT 1
+        ) AS last_trade
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(symbols)
+            .bind(at)
+            .bind(not_before)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Aggregates the tick history of `symbol` into OHLCV candles covering `[from, to)`.
+///
+/// History rows carry the range of their batch and fall into the candle of their last trade.
+pub async fn get_candles(pool: Arc<PgPool>, symbol: String, interval: CandleInterval, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>) -> Vec<Candle> {
+    let statement = "
+        SELECT
+            date_bin($2::INTERVAL, traded_at, TIMESTAMPTZ '2000-01-01 00:00:00+00') AS time,
+            (array_agg(COALESCE(open, price) ORDER BY traded_at ASC))[1] AS open,
+            MAX(COALESCE(high, price)) AS high,
+            MIN(COALESCE(low, price)) AS low,
+            (array_agg(price ORDER BY traded_at DESC))[1] AS close,
+            SUM(volume) AS volume,
+            SUM(trade_count)::BIGINT AS trade_count
+        FROM trade_history
+        WHERE symbol = $1
+            AND traded_at >= $3
+            AND traded_at < $4
+        GROUP BY time
+        ORDER BY time ASC
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        let result: Result<Vec<Candle>, sqlx::Error> = query_as(statement)
+            .bind(symbol)
+            .bind(interval.as_pg_interval())
+            .bind(from)
+            .bind(to)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e));
+
+        result.unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Returns the number of deleted rows.
+pub async fn delete_trade_history_before(pool: Arc<PgPool>, before: chrono::DateTime<Utc>) -> u64 {
+    let statement = "
+        DELETE FROM trade_history
+            WHERE traded_at < $1
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(before)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .map_or(0, |result| result.rows_affected())
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        0
+    }
+}
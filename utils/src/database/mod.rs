@@ -7,9 +7,24 @@ pub use sqlx::PgPool;
 #[cfg(feature = "finance")]
 pub mod finance;
 
+#[cfg(feature = "finance")]
+pub mod watchlists;
+
+#[cfg(feature = "finance")]
+pub mod alerts;
+
+#[cfg(feature = "finance")]
+pub mod news;
+
+#[cfg(feature = "finance")]
+pub mod portfolios;
+
 #[cfg(feature = "sports")]
 pub mod sports;
 
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+
 pub async fn initialize_pool() -> Result<PgPool> {
     let get_env_var = |key: &str| -> Result<String> {
         env::var(key).with_context(|| format!("Missing environment variable: {}", key))
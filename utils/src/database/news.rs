@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use log::error;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, query, query_as};
+
+/// A stored headline and the subscribed symbols it was fetched for, empty for general market news.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct NewsHeadline {
+    pub id: i64,
+    #[serde(skip)]
+    pub provider: String,
+    #[serde(skip)]
+    pub provider_id: String,
+    pub headline: String,
+    pub summary: Option<String>,
+    pub source: Option<String>,
+    pub url: String,
+    pub image_url: Option<String>,
+    pub published_at: chrono::DateTime<Utc>,
+    pub symbols: Vec<String>,
+}
+
+pub async fn create_tables(pool: Arc<PgPool>) {
+    // Providers repost the same story under a new id or a new URL, either one identifies it.
+    let articles_statement = "
+        CREATE TABLE IF NOT EXISTS news_articles (
+            id BIGSERIAL PRIMARY KEY,
+            provider VARCHAR(20) NOT NULL,
+            provider_id TEXT NOT NULL,
+            headline TEXT NOT NULL,
+            summary TEXT,
+            source TEXT,
+            url TEXT NOT NULL UNIQUE,
+            image_url TEXT,
+            published_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            fetched_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (provider, provider_id)
+        );
+    ";
+
+    let articles_index_statement = "
+        CREATE INDEX IF NOT EXISTS news_articles_published_at_idx
+            ON news_articles (published_at DESC);
+    ";
+
+    let symbols_statement = "
+        CREATE TABLE IF NOT EXISTS news_symbols (
+            article_id BIGINT NOT NULL REFERENCES news_articles (id) ON DELETE CASCADE,
+            symbol VARCHAR(30) NOT NULL,
+            PRIMARY KEY (article_id, symbol)
+        );
+    ";
+
+    let symbols_index_statement = "
+        CREATE INDEX IF NOT EXISTS news_symbols_symbol_idx
+            ON news_symbols (symbol);
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        for statement in [articles_statement, articles_index_statement, symbols_statement, symbols_index_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// Stores the headlines that are not known yet and links all of them to `symbol`, if given.
+/// `id` and `symbols` of the headlines are ignored. Returns how many headlines were new.
+pub async fn insert_news(pool: Arc<PgPool>, headlines: Vec<NewsHeadline>, symbol: Option<String>) -> u64 {
+    let articles_statement = "
+        INSERT INTO news_articles (provider, provider_id, headline, summary, source, url, image_url, published_at)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::TEXT[], $7::TEXT[], $8::TIMESTAMPTZ[])
+        ON CONFLICT DO NOTHING
+    ";
+
+    let symbols_statement = "
+        INSERT INTO news_symbols (article_id, symbol)
+            SELECT id, $3 FROM news_articles
+            WHERE url = ANY($1) OR (provider, provider_id) IN (SELECT * FROM UNNEST($2::VARCHAR[], $4::TEXT[]))
+        ON CONFLICT DO NOTHING
+    ";
+
+    if headlines.is_empty() {
+        return 0;
+    }
+
+    let mut providers = Vec::with_capacity(headlines.len());
+    let mut provider_ids = Vec::with_capacity(headlines.len());
+    let mut titles = Vec::with_capacity(headlines.len());
+    let mut summaries = Vec::with_capacity(headlines.len());
+    let mut sources = Vec::with_capacity(headlines.len());
+    let mut urls = Vec::with_capacity(headlines.len());
+    let mut image_urls = Vec::with_capacity(headlines.len());
+    let mut published_ats = Vec::with_capacity(headlines.len());
+
+    for headline in headlines {
+        providers.push(headline.provider);
+        provider_ids.push(headline.provider_id);
+        titles.push(headline.headline);
+        summaries.push(headline.summary);
+        sources.push(headline.source);
+        urls.push(headline.url);
+        image_urls.push(headline.image_url);
+        published_ats.push(headline.published_at);
+    }
+
+    let Ok(mut transaction) = pool.begin().await else {
+        error!("Connection Error: Failed to begin a transaction");
+        return 0;
+    };
+
+    let result = query(articles_statement)
+        .bind(&providers)
+        .bind(&provider_ids)
+        .bind(titles)
+        .bind(summaries)
+        .bind(sources)
+        .bind(&urls)
+        .bind(image_urls)
+        .bind(published_ats)
+        .execute(&mut *transaction)
+        .await;
+
+    let inserted = match result {
+        Ok(result) => result.rows_affected(),
+        Err(e) => {
+            error!("Execution Error: {}", e);
+            return 0;
+        }
+    };
+
+    if let Some(symbol) = symbol {
+        let result = query(symbols_statement)
+            .bind(urls)
+            .bind(providers)
+            .bind(symbol)
+            .bind(provider_ids)
+            .execute(&mut *transaction)
+            .await;
+
+        if let Err(e) = result {
+            error!("Execution Error: {}", e);
+            return 0;
+        }
+    }
+
+    match transaction.commit().await {
+        Ok(_) => inserted,
+        Err(e) => {
+            error!("Execution Error: {}", e);
+            0
+        }
+    }
+}
+
+/// Latest headlines first, only those linked to `symbol` if given.
+pub async fn get_news(pool: Arc<PgPool>, symbol: Option<String>, limit: i64) -> Vec<NewsHeadline> {
+    let statement = "
+        SELECT
+            news_articles.id,
+            news_articles.provider,
+            news_articles.provider_id,
+            news_articles.headline,
+            news_articles.summary,
+            news_articles.source,
+            news_articles.url,
+            news_articles.image_url,
+            news_articles.published_at,
+            COALESCE(ARRAY_AGG(news_symbols.symbol ORDER BY news_symbols.symbol) FILTER (WHERE news_symbols.symbol IS NOT NULL), '{}') as symbols
+        FROM news_articles
+        LEFT JOIN news_symbols ON news_symbols.article_id = news_articles.id
+        WHERE $1::VARCHAR IS NULL OR EXISTS (
+            SELECT 1 FROM news_symbols AS linked
+            WHERE linked.article_id = news_articles.id AND linked.symbol = $1
+        )
+        GROUP BY news_articles.id
+        ORDER BY news_articles.published_at DESC
+        LIMIT $2
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(symbol)
+            .bind(limit)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Returns the number of headlines removed.
+pub async fn delete_news_before(pool: Arc<PgPool>, before: chrono::DateTime<Utc>) -> u64 {
+    let statement = "
+        DELETE FROM news_articles
+            WHERE published_at < $1
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(before)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .map_or(0, |result| result.rows_affected())
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        0
+    }
+}
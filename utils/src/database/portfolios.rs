@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use log::error;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, query, query_as};
+
+/// A purchase of a holding.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortfolioLot {
+    pub quantity: Decimal,
+    /// Total amount paid for `quantity`, fees included.
+    pub cost_basis: Decimal,
+    pub acquired_on: Option<NaiveDate>,
+}
+
+/// A user's position in one symbol, made up of one or more lots.
+#[derive(Serialize, Debug, Clone)]
+pub struct Holding {
+    pub symbol: String,
+    pub lots: Vec<PortfolioLot>,
+}
+
+impl Holding {
+    pub fn quantity(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    pub fn cost_basis(&self) -> Decimal {
+        self.lots.iter().map(|lot| lot.cost_basis).sum()
+    }
+}
+
+pub async fn create_tables(pool: Arc<PgPool>) {
+    let lots_statement = "
+        CREATE TABLE IF NOT EXISTS portfolio_lots (
+            id BIGSERIAL PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            symbol VARCHAR(30) NOT NULL,
+            position INTEGER NOT NULL,
+            quantity NUMERIC NOT NULL,
+            cost_basis NUMERIC NOT NULL,
+            acquired_on DATE,
+            UNIQUE (user_id, symbol, position)
+        );
+    ";
+
+    let symbol_index_statement = "
+        CREATE INDEX IF NOT EXISTS portfolio_lots_symbol_idx
+            ON portfolio_lots (symbol);
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        for statement in [lots_statement, symbol_index_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// The user's holdings ordered by symbol, their lots in the order they were given.
+pub async fn get_holdings(pool: Arc<PgPool>, user_id: String) -> Vec<Holding> {
+    let statement = "
+        SELECT symbol, quantity, cost_basis, acquired_on
+        FROM portfolio_lots
+        WHERE user_id = $1
+        ORDER BY symbol ASC, position ASC
+    ";
+
+    let conn = pool.acquire().await;
+
+    let rows: Vec<(String, Decimal, Decimal, Option<NaiveDate>)> = if let Ok(mut connection) = conn {
+        query_as(statement)
+            .bind(user_id)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        return Vec::new();
+    };
+
+    let mut holdings: Vec<Holding> = Vec::new();
+    for (symbol, quantity, cost_basis, acquired_on) in rows {
+        let lot = PortfolioLot { quantity, cost_basis, acquired_on };
+
+        match holdings.last_mut() {
+            Some(holding) if holding.symbol == symbol => holding.lots.push(lot),
+            _ => holdings.push(Holding { symbol, lots: vec![lot] }),
+        }
+    }
+
+    holdings
+}
+
+/// Replaces every lot of the user's holding in `symbol`. Returns `false` if it could not be stored.
+pub async fn replace_holding(pool: Arc<PgPool>, user_id: String, symbol: String, lots: Vec<PortfolioLot>) -> bool {
+    let delete_statement = "
+        DELETE FROM portfolio_lots
+            WHERE user_id = $1 AND symbol = $2
+    ";
+
+    let insert_statement = "
+        INSERT INTO portfolio_lots (user_id, symbol, position, quantity, cost_basis, acquired_on)
+            SELECT $1, $2, position, quantity, cost_basis, acquired_on
+            FROM UNNEST($3::NUMERIC[], $4::NUMERIC[], $5::DATE[]) WITH ORDINALITY AS lots(quantity, cost_basis, acquired_on, position)
+    ";
+
+    let mut quantities = Vec::with_capacity(lots.len());
+    let mut cost_bases = Vec::with_capacity(lots.len());
+    let mut acquired_ons = Vec::with_capacity(lots.len());
+
+    for lot in lots {
+        quantities.push(lot.quantity);
+        cost_bases.push(lot.cost_basis);
+        acquired_ons.push(lot.acquired_on);
+    }
+
+    let Ok(mut transaction) = pool.begin().await else {
+        error!("Connection Error: Failed to begin a transaction");
+        return false;
+    };
+
+    let result = query(delete_statement)
+        .bind(&user_id)
+        .bind(&symbol)
+        .execute(&mut *transaction)
+        .await;
+
+    let result = match result {
+        Ok(_) => query(insert_statement)
+            .bind(user_id)
+            .bind(symbol)
+            .bind(quantities)
+            .bind(cost_bases)
+            .bind(acquired_ons)
+            .execute(&mut *transaction)
+            .await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(_) => transaction.commit().await.inspect_err(|e| error!("Execution Error: {}", e)).is_ok(),
+        Err(e) => {
+            error!("Execution Error: {}", e);
+            false
+        }
+    }
+}
+
+/// Returns `false` if the user holds no such symbol.
+pub async fn delete_holding(pool: Arc<PgPool>, user_id: String, symbol: String) -> bool {
+    let statement = "
+        DELETE FROM portfolio_lots
+            WHERE user_id = $1 AND symbol = $2
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(user_id)
+            .bind(symbol)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok_and(|result| result.rows_affected() > 0)
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        false
+    }
+}
+
+/// Every symbol held in any portfolio, these stay subscribed.
+pub async fn get_portfolio_symbols(pool: Arc<PgPool>) -> Result<Vec<String>, sqlx::Error> {
+    let statement = "
+        SELECT DISTINCT symbol
+        FROM portfolio_lots
+        ORDER BY symbol ASC
+    ";
+
+    let mut connection = pool.acquire().await
+        .inspect_err(|_| error!("Connection Error: Failed to acquire a connection from the pool"))?;
+
+    let rows: Vec<(String,)> = query_as(statement)
+        .fetch_all(&mut *connection)
+        .await
+        .inspect_err(|e| error!("Execution Error: {}", e))?;
+
+    Ok(rows.into_iter().map(|(symbol,)| symbol).collect())
+}
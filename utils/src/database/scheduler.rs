@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use log::error;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, query, query_as};
+
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct JobRun {
+    pub job_name: String,
+    pub started_at: chrono::DateTime<Utc>,
+    pub duration_ms: i64,
+    /// `success` or `failure`.
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+pub async fn create_tables(pool: Arc<PgPool>) {
+    let runs_statement = "
+        CREATE TABLE IF NOT EXISTS scheduled_job_runs (
+            id BIGSERIAL PRIMARY KEY,
+            job_name VARCHAR(100) NOT NULL,
+            started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            duration_ms BIGINT NOT NULL,
+            outcome VARCHAR(10) NOT NULL,
+            error TEXT
+        );
+    ";
+
+    let runs_index_statement = "
+        CREATE INDEX IF NOT EXISTS scheduled_job_runs_job_name_started_at_idx
+            ON scheduled_job_runs (job_name, started_at DESC);
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        for statement in [runs_statement, runs_index_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+pub async fn insert_job_run(pool: Arc<PgPool>, run: JobRun) {
+    let statement = "
+        INSERT INTO scheduled_job_runs (job_name, started_at, duration_ms, outcome, error)
+            VALUES ($1, $2, $3, $4, $5)
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        let _ = query(statement)
+            .bind(run.job_name)
+            .bind(run.started_at)
+            .bind(run.duration_ms)
+            .bind(run.outcome)
+            .bind(run.error)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e));
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+/// Most recent run of every job that has run at least once.
+pub async fn get_last_job_runs(pool: Arc<PgPool>) -> Vec<JobRun> {
+    let statement = "
+        SELECT DISTINCT ON (job_name)
+            job_name,
+            started_at,
+            duration_ms,
+            outcome,
+            error
+        FROM scheduled_job_runs
+        ORDER BY job_name, started_at DESC
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(statement)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+/// Returns the number of deleted runs.
+pub async fn delete_job_runs_before(pool: Arc<PgPool>, before: chrono::DateTime<Utc>) -> u64 {
+    let statement = "
+        DELETE FROM scheduled_job_runs
+            WHERE started_at < $1
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(before)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .map_or(0, |result| result.rows_affected())
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        0
+    }
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+pub use uuid::Uuid;
+use chrono::Utc;
+use log::error;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, Postgres, Transaction, query, query_as};
+
+/// A user's named selection of symbols.
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct Watchlist {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: String,
+    pub name: String,
+    pub symbols: Vec<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+const SELECT_WATCHLISTS: &str = "
+    SELECT
+        watchlists.id,
+        watchlists.user_id,
+        watchlists.name,
+        COALESCE(ARRAY_AGG(watchlist_symbols.symbol ORDER BY watchlist_symbols.position) FILTER (WHERE watchlist_symbols.symbol IS NOT NULL), '{}') as symbols,
+        watchlists.created_at,
+        watchlists.updated_at
+    FROM watchlists
+    LEFT JOIN watchlist_symbols ON watchlist_symbols.watchlist_id = watchlists.id
+";
+
+pub async fn create_tables(pool: Arc<PgPool>) {
+    let watchlists_statement = "
+        CREATE TABLE IF NOT EXISTS watchlists (
+            id UUID PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+    ";
+
+    let watchlists_index_statement = "
+        CREATE INDEX IF NOT EXISTS watchlists_user_id_idx
+            ON watchlists (user_id);
+    ";
+
+    let symbols_statement = "
+        CREATE TABLE IF NOT EXISTS watchlist_symbols (
+            watchlist_id UUID NOT NULL REFERENCES watchlists (id) ON DELETE CASCADE,
+            symbol VARCHAR(30) NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (watchlist_id, symbol)
+        );
+    ";
+
+    let symbols_index_statement = "
+        CREATE INDEX IF NOT EXISTS watchlist_symbols_symbol_idx
+            ON watchlist_symbols (symbol);
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        for statement in [watchlists_statement, watchlists_index_statement, symbols_statement, symbols_index_statement] {
+            let _ = query(statement)
+                .execute(&mut *connection)
+                .await
+                .inspect_err(|e| error!("Execution Error: {}", e));
+        }
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+    }
+}
+
+pub async fn get_watchlists(pool: Arc<PgPool>, user_id: String) -> Vec<Watchlist> {
+    let statement = format!("{SELECT_WATCHLISTS} WHERE watchlists.user_id = $1 GROUP BY watchlists.id ORDER BY watchlists.created_at ASC");
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(&statement)
+            .bind(user_id)
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .unwrap_or_default()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        Vec::new()
+    }
+}
+
+pub async fn get_watchlist(pool: Arc<PgPool>, id: Uuid) -> Option<Watchlist> {
+    let statement = format!("{SELECT_WATCHLISTS} WHERE watchlists.id = $1 GROUP BY watchlists.id");
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query_as(&statement)
+            .bind(id)
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .ok()
+            .flatten()
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        None
+    }
+}
+
+/// Returns `false` if the watchlist could not be stored.
+pub async fn insert_watchlist(pool: Arc<PgPool>, id: Uuid, user_id: String, name: String, symbols: Vec<String>) -> bool {
+    let statement = "
+        INSERT INTO watchlists (id, user_id, name)
+            VALUES ($1, $2, $3)
+    ";
+
+    let Ok(mut transaction) = pool.begin().await else {
+        error!("Connection Error: Failed to begin a transaction");
+        return false;
+    };
+
+    let result = query(statement)
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .execute(&mut *transaction)
+        .await;
+
+    if let Err(e) = result {
+        error!("Execution Error: {}", e);
+        return false;
+    }
+
+    if !replace_symbols(&mut transaction, id, symbols).await {
+        return false;
+    }
+
+    transaction.commit().await.inspect_err(|e| error!("Execution Error: {}", e)).is_ok()
+}
+
+/// Renames the watchlist and/or replaces its symbols, only if it belongs to `user_id`.
+/// Returns `false` if no such watchlist exists.
+pub async fn update_watchlist(pool: Arc<PgPool>, id: Uuid, user_id: String, name: Option<String>, symbols: Option<Vec<String>>) -> bool {
+    let statement = "
+        UPDATE watchlists
+            SET name = COALESCE($3, name),
+                updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND user_id = $2
+    ";
+
+    let Ok(mut transaction) = pool.begin().await else {
+        error!("Connection Error: Failed to begin a transaction");
+        return false;
+    };
+
+    let result = query(statement)
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .execute(&mut *transaction)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() > 0 => {}
+        Ok(_) => return false,
+        Err(e) => {
+            error!("Execution Error: {}", e);
+            return false;
+        }
+    }
+
+    if let Some(symbols) = symbols && !replace_symbols(&mut transaction, id, symbols).await {
+        return false;
+    }
+
+    transaction.commit().await.inspect_err(|e| error!("Execution Error: {}", e)).is_ok()
+}
+
+/// Returns `true` if the watchlist existed and belonged to `user_id`.
+pub async fn delete_watchlist(pool: Arc<PgPool>, id: Uuid, user_id: String) -> bool {
+    let statement = "
+        DELETE FROM watchlists
+            WHERE id = $1 AND user_id = $2
+    ";
+
+    let conn = pool.acquire().await;
+
+    if let Ok(mut connection) = conn {
+        query(statement)
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| error!("Execution Error: {}", e))
+            .is_ok_and(|result| result.rows_affected() > 0)
+    } else {
+        error!("Connection Error: Failed to acquire a connection from the pool");
+        false
+    }
+}
+
+/// Every symbol referenced by at least one watchlist.
+pub async fn get_watchlist_symbols(pool: Arc<PgPool>) -> Result<Vec<String>, sqlx::Error> {
+    let statement = "
+        SELECT DISTINCT symbol
+        FROM watchlist_symbols
+        ORDER BY symbol ASC
+    ";
+
+    let mut connection = pool.acquire().await
+        .inspect_err(|_| error!("Connection Error: Failed to acquire a connection from the pool"))?;
+
+    let rows: Vec<(String,)> = query_as(statement)
+        .fetch_all(&mut *connection)
+        .await
+        .inspect_err(|e| error!("Execution Error: {}", e))?;
+
+    Ok(rows.into_iter().map(|(symbol,)| symbol).collect())
+}
+
+async fn replace_symbols(transaction: &mut Transaction<'_, Postgres>, id: Uuid, symbols: Vec<String>) -> bool {
+    let delete_statement = "
+        DELETE FROM watchlist_symbols
+            WHERE watchlist_id = $1
+    ";
+
+    let insert_statement = "
+        INSERT INTO watchlist_symbols (watchlist_id, symbol, position)
+            SELECT $1, symbol, position::INTEGER
+            FROM UNNEST($2::VARCHAR[]) WITH ORDINALITY AS entries (symbol, position)
+    ";
+
+    let result = query(delete_statement)
+        .bind(id)
+        .execute(&mut **transaction)
+        .await;
+
+    let result = match result {
+        Ok(_) => query(insert_statement)
+            .bind(id)
+            .bind(symbols)
+            .execute(&mut **transaction)
+            .await,
+        Err(e) => Err(e),
+    };
+
+    result.inspect_err(|e| error!("Execution Error: {}", e)).is_ok()
+}